@@ -0,0 +1,202 @@
+use crate::bot::Module;
+use crate::db::{delete_acl, get_conn, get_role, list_acl, set_owner, set_role};
+use crate::dispatcher::{Callback, Context, Dispatcher};
+use crate::error::report;
+use crate::utils::{send_content, Content};
+use async_trait::async_trait;
+use log::info;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// ordered from the least to the most privileged
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Blocked,
+    /// receives notifications and can list
+    Reader,
+    /// can manage subscriptions
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Blocked => "blocked",
+            Role::Reader => "reader",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "blocked" => Ok(Role::Blocked),
+            "reader" => Ok(Role::Reader),
+            "admin" => Ok(Role::Admin),
+            "owner" => Ok(Role::Owner),
+            _ => Err(()),
+        }
+    }
+}
+
+fn role_of(id: &str) -> Option<Role> {
+    match get_role(&get_conn(), id) {
+        Ok(role) => role.and_then(|r| r.parse().ok()),
+        Err(e) => {
+            report(e);
+            None
+        }
+    }
+}
+
+/// the role of a user in a chat: a blocked user stays blocked, otherwise a
+/// group allowed as a whole lends its role to every member
+pub fn effective_role(cid: &str, uid: &str) -> Option<Role> {
+    let user = role_of(uid);
+    if cid == uid || user == Some(Role::Blocked) {
+        return user;
+    }
+    user.max(role_of(cid))
+}
+
+/// the owner always comes from `MASTER_ID`, a former one is an admin now
+pub fn bootstrap(master: &str) {
+    match set_owner(&mut get_conn(), master) {
+        Ok(0) => {}
+        Ok(n) => info!("{} former owners are admins now", n),
+        Err(e) => {
+            report(e);
+        }
+    }
+}
+
+/// send a notification to everyone who may read it
+pub async fn notify(content: Content) {
+    notify_roles(content, Role::Reader..=Role::Owner).await;
+}
+
+/// send a notification to everyone with one of the `roles`
+pub async fn notify_roles(content: Content, roles: RangeInclusive<Role>) {
+    let users = list_acl(&get_conn()).unwrap_or_else(|e| {
+        report(e);
+        vec![]
+    });
+    for acl in users {
+        if acl.role.parse::<Role>().is_ok_and(|r| roles.contains(&r)) {
+            send_content(&acl.user, content.clone()).await;
+        }
+    }
+}
+
+struct Allow {}
+
+#[async_trait]
+impl Callback for Allow {
+    async fn callback(&self, ctx: &Context) {
+        let (Some(user), Some(role)) = (ctx.arg(0), ctx.arg(1)) else {
+            ctx.reply("need user id and role").await;
+            return;
+        };
+        if user.parse::<i64>().is_err() {
+            ctx.reply("user should be a numeric id").await;
+            return;
+        }
+        let role = match role.parse::<Role>() {
+            Ok(Role::Owner) => {
+                ctx.reply("there is only one owner").await;
+                return;
+            }
+            Ok(role) => role,
+            Err(_) => {
+                ctx.reply("role should be admin, reader or blocked").await;
+                return;
+            }
+        };
+        if role_of(user) == Some(Role::Owner) {
+            ctx.reply("can not change the owner").await;
+            return;
+        }
+        let reply = match set_role(&get_conn(), user, role.as_str()) {
+            Ok(_) => format!("{} is {} now", user, role.as_str()),
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        ctx.reply(&reply).await;
+    }
+}
+
+struct Deny {}
+
+#[async_trait]
+impl Callback for Deny {
+    async fn callback(&self, ctx: &Context) {
+        let Some(user) = ctx.arg(0) else {
+            ctx.reply("need user id").await;
+            return;
+        };
+        if role_of(user) == Some(Role::Owner) {
+            ctx.reply("can not deny the owner").await;
+            return;
+        }
+        let reply = match delete_acl(&get_conn(), user) {
+            Ok(n) => {
+                if n > 0 {
+                    "done"
+                } else {
+                    "not found"
+                }
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        ctx.reply(reply).await;
+    }
+}
+
+struct Users {}
+
+#[async_trait]
+impl Callback for Users {
+    async fn callback(&self, ctx: &Context) {
+        let rs = match list_acl(&get_conn()) {
+            Ok(rs) => rs,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let reply = rs
+            .into_iter()
+            .map(|r| format!("{} {}", r.user, r.role))
+            .collect::<Vec<_>>()
+            .join("\n");
+        if reply.is_empty() {
+            ctx.reply("no results").await;
+        } else {
+            ctx.reply(&reply).await;
+        }
+    }
+}
+
+/// who may use the bot: `/allow`, `/deny` and `/users`
+pub struct AclModule {}
+
+impl Module for AclModule {
+    fn name(&self) -> &'static str {
+        "acl"
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        dispatcher.register("/allow", Role::Owner, Box::new(Allow {}));
+        dispatcher.register("/deny", Role::Owner, Box::new(Deny {}));
+        dispatcher.register("/users", Role::Owner, Box::new(Users {}));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::acl::{self, Role};
+use crate::db;
+use crate::dispatcher::{Callback, Dispatcher, Middleware};
+use crate::error::MyError;
+use crate::middleware;
+use crate::utils::{check_settings, recv, sleep, Content, Target};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use log::{error, info};
+use rusqlite::Connection;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// where messages come from and go to, like telegram
+#[async_trait]
+pub trait Transport {
+    /// connect, and give the bot's username that commands may be addressed to
+    async fn start(&self) -> Result<String, MyError>;
+
+    /// wait for the next incoming messages and dispatch them
+    async fn poll(&self, dispatcher: &Dispatcher) -> Result<(), MyError>;
+
+    async fn send(&self, target: Target, content: Content) -> Result<(), MyError>;
+
+    /// whether the bot may post in a chat given by id or name, and the
+    /// chat's id if so
+    async fn can_post(&self, chat: &str) -> Result<String, MyError> {
+        Ok(chat.to_owned())
+    }
+}
+
+/// the names of the modules the bot runs with
+static ENABLED: OnceLock<Vec<&'static str>> = OnceLock::new();
+
+/// whether the module is running, for modules that work with the others
+pub fn enabled(name: &str) -> bool {
+    ENABLED.get().is_some_and(|names| names.contains(&name))
+}
+
+/// the transport of the running bot, for callbacks that need to ask it
+static TRANSPORT: OnceLock<Arc<dyn Transport + Send + Sync>> = OnceLock::new();
+
+/// see `Transport::can_post`
+pub async fn can_post(chat: &str) -> Result<String, MyError> {
+    match TRANSPORT.get() {
+        Some(transport) => transport.can_post(chat).await,
+        None => Err(MyError::NoTransport),
+    }
+}
+
+/// a part of the bot: its tables, commands, and maybe a task that runs as
+/// long as the bot; a disabled module contributes none of them
+pub trait Module {
+    /// what `Builder::disable` and `enabled` know it by
+    fn name(&self) -> &'static str;
+
+    /// create or update its tables, before anything runs
+    fn migrate(&self, _conn: &Connection) -> rusqlite::Result<usize> {
+        Ok(0)
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher);
+
+    /// check the settings it needs, like `utils::check_settings` does for
+    /// the common ones; an error keeps the bot from starting
+    fn check_settings(&self) -> Result<(), MyError> {
+        Ok(())
+    }
+
+    /// like polling subscriptions, spawned when the bot starts
+    fn task(&self) -> Option<BoxFuture<'static, ()>> {
+        None
+    }
+}
+
+/// sets up and runs a bot, see `Bot::builder`
+pub struct Builder {
+    transport: Option<Arc<dyn Transport + Send + Sync>>,
+    database: String,
+    owner: Option<String>,
+    modules: Vec<Box<dyn Module>>,
+    disabled: Vec<String>,
+    commands: Vec<(String, Role, Box<dyn Callback + Sync>)>,
+    middlewares: Vec<Box<dyn Middleware + Sync>>,
+}
+
+pub struct Bot {}
+
+impl Bot {
+    /// a bot with the default middlewares: panics are caught, commands are
+    /// audited, checked against the acl, rate limited and timed
+    pub fn builder() -> Builder {
+        Builder {
+            transport: None,
+            database: "data.db".to_owned(),
+            owner: None,
+            modules: vec![],
+            disabled: vec![],
+            commands: vec![],
+            middlewares: vec![
+                Box::new(middleware::CatchPanic {}),
+                Box::new(middleware::Audit {}),
+                Box::new(middleware::Auth {}),
+                Box::new(middleware::RateLimit::new(20, Duration::from_secs(60))),
+                Box::new(middleware::Timing::new(Duration::from_secs(10))),
+            ],
+        }
+    }
+}
+
+impl Builder {
+    pub fn transport(mut self, transport: impl Transport + Send + Sync + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// the sqlite file to keep everything in, `data.db` by default
+    pub fn database(mut self, path: &str) -> Self {
+        self.database = path.to_owned();
+        self
+    }
+
+    /// the user who can not be locked out, see `acl::bootstrap`
+    pub fn owner(mut self, id: &str) -> Self {
+        self.owner = Some(id.to_owned());
+        self
+    }
+
+    pub fn module(mut self, module: impl Module + 'static) -> Self {
+        self.modules.push(Box::new(module));
+        self
+    }
+
+    /// like `module` for several, such as every one in `turtlebot::modules`
+    pub fn modules(mut self, modules: Vec<Box<dyn Module>>) -> Self {
+        self.modules.extend(modules);
+        self
+    }
+
+    /// leave out a module added before, by its name
+    pub fn disable(mut self, name: &str) -> Self {
+        self.disabled.push(name.to_owned());
+        self
+    }
+
+    /// a command of your own, registered after the modules so it can
+    /// replace one of theirs
+    pub fn command(
+        mut self,
+        cmd: &str,
+        role: Role,
+        callback: impl Callback + Sync + 'static,
+    ) -> Self {
+        self.commands
+            .push((cmd.to_owned(), role, Box::new(callback)));
+        self
+    }
+
+    /// a middleware inside the default ones
+    pub fn middleware(mut self, middleware: impl Middleware + Sync + 'static) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// run until the transport fails to start, polling and sending forever
+    pub async fn run(self) -> Result<(), MyError> {
+        let transport = self.transport.ok_or(MyError::NoTransport)?;
+        let disabled = self.disabled;
+        let modules = self
+            .modules
+            .into_iter()
+            .filter(|m| !disabled.iter().any(|name| name == m.name()))
+            .collect::<Vec<_>>();
+        let names = modules.iter().map(|m| m.name()).collect::<Vec<_>>();
+        info!("modules: {}", names.join(", "));
+        let _ = ENABLED.set(names);
+        check_settings()?;
+        for module in &modules {
+            module.check_settings()?;
+        }
+        db::set_path(&self.database);
+        db::init()?;
+        let conn = db::get_conn();
+        for module in &modules {
+            module.migrate(&conn)?;
+        }
+        drop(conn);
+        if let Some(owner) = &self.owner {
+            acl::bootstrap(owner);
+        }
+        let mut dispatcher = Dispatcher::default();
+        for middleware in self.middlewares {
+            dispatcher.wrap(middleware);
+        }
+        for module in &modules {
+            module.register(&mut dispatcher);
+        }
+        for (cmd, role, callback) in self.commands {
+            dispatcher.register(&cmd, role, callback);
+        }
+        dispatcher.set_username(&transport.start().await?);
+        let _ = TRANSPORT.set(transport.clone());
+        for task in modules.iter().filter_map(|m| m.task()) {
+            tokio::spawn(task);
+        }
+        // sending goes on while a command is handled, so it can send plenty
+        let sender = transport.clone();
+        tokio::spawn(async move {
+            while let Some((target, content)) = recv().await {
+                if let Err(err) = sender.send(target, content).await {
+                    error!("[{}] send error: {}", err.label(), err);
+                }
+            }
+            error!("channel recv error");
+        });
+        info!("running");
+        loop {
+            if let Err(err) = transport.poll(&dispatcher).await {
+                error!("[{}] poll error: {}", err.label(), err);
+                sleep(1).await;
+            }
+        }
+    }
+}
@@ -1,11 +1,159 @@
+use crate::utils::{canonical_repo, canonical_url};
 use rusqlite::{params, Connection, Result, Row};
+use std::sync::OnceLock;
+
+static PATH: OnceLock<String> = OnceLock::new();
+
+/// use another file than `data.db`, before the first connection
+pub fn set_path(path: &str) {
+    if PATH.set(path.to_owned()).is_err() {
+        log::warn!("db path is set already");
+    }
+}
 
 pub fn get_conn() -> Connection {
-    Connection::open("data.db").unwrap()
+    Connection::open(PATH.get().map(String::as_str).unwrap_or("data.db")).unwrap()
+}
+
+/// a fresh database file for the tests, one per run; the tables of a
+/// module are up to its tests
+#[cfg(all(test, feature = "rss"))]
+pub(crate) fn test_db() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let path = std::env::temp_dir().join(format!("turtlebot-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        set_path(path.to_str().unwrap());
+        init().unwrap();
+    });
+}
+
+/// add a column to a table created by an older version, if it is not there yet
+fn add_column(conn: &Connection, table: &str, column: &str, def: &str) -> Result<usize> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1",
+            table
+        ))?
+        .exists(params![column])?;
+    if exists {
+        return Ok(0);
+    }
+    conn.execute(
+        &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, def),
+        params![],
+    )
+}
+
+/// columns to track how polling a subscription goes
+fn add_health_columns(conn: &Connection, table: &str) -> Result<usize> {
+    add_column(conn, table, "last_attempt", "INTEGER")
+        .and(add_column(conn, table, "last_success", "INTEGER"))
+        .and(add_column(conn, table, "last_status", "INTEGER"))
+        .and(add_column(
+            conn,
+            table,
+            "failures",
+            "INTEGER NOT NULL DEFAULT 0",
+        ))
+        .and(add_column(conn, table, "last_error", "TEXT"))
+        .and(add_column(
+            conn,
+            table,
+            "paused",
+            "INTEGER NOT NULL DEFAULT 0",
+        ))
 }
 
+fn add_pause_columns(conn: &Connection, table: &str) -> Result<usize> {
+    add_column(conn, table, "pause_since", "INTEGER").and(add_column(
+        conn,
+        table,
+        "pause_until",
+        "INTEGER",
+    ))
+}
+
+/// fill the canonical column of rows that predate it, leaving duplicates
+/// empty so that the unique index can still be built
+fn fill_canon(
+    conn: &Connection,
+    table: &str,
+    source: &str,
+    f: fn(&str) -> String,
+) -> Result<usize> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, {} from {} where canon IS NULL order by id asc",
+        source, table
+    ))?;
+    let rows = stmt
+        .query_map(params![], |r| {
+            Ok((r.get::<_, i32>(0)?, r.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    let mut filled = 0;
+    for (id, value) in rows {
+        filled += conn.execute(
+            &format!(
+                "UPDATE {0} set canon = ?1 where id = ?2
+  and NOT EXISTS (SELECT 1 from {0} where canon = ?1)",
+                table
+            ),
+            params![f(&value), id],
+        )?;
+    }
+    Ok(filled)
+}
+
+/// the tables every bot has, the modules add theirs with `Module::migrate`
 pub fn init() -> Result<usize> {
     let conn = get_conn();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS entry (
+  id INTEGER PRIMARY KEY NOT NULL,
+  kind TEXT NOT NULL,
+  sub_id INTEGER NOT NULL,
+  source TEXT NOT NULL,
+  title TEXT NOT NULL,
+  link TEXT NOT NULL,
+  summary TEXT NOT NULL,
+  time INTEGER NOT NULL)",
+        params![],
+    )
+    .and(conn.execute(
+        "CREATE INDEX IF NOT EXISTS entry_kind ON entry (kind, sub_id)",
+        params![],
+    ))
+    .and(conn.execute(
+        "CREATE TABLE IF NOT EXISTS dialogue (
+  cid TEXT NOT NULL,
+  uid TEXT NOT NULL,
+  cmd TEXT NOT NULL,
+  step TEXT NOT NULL,
+  data TEXT NOT NULL,
+  expires INTEGER NOT NULL,
+  PRIMARY KEY (cid, uid))",
+        params![],
+    ))
+    .and(conn.execute(
+        "CREATE TABLE IF NOT EXISTS route (
+  kind TEXT NOT NULL,
+  sub_id INTEGER NOT NULL,
+  chat TEXT NOT NULL,
+  thread INTEGER,
+  PRIMARY KEY (kind, sub_id))",
+        params![],
+    ))
+    .and(conn.execute(
+        "CREATE TABLE IF NOT EXISTS acl (
+  user TEXT PRIMARY KEY NOT NULL,
+  role TEXT NOT NULL)",
+        params![],
+    ))
+}
+
+/// feeds, what changed about them and the posts already seen
+pub fn init_rss(conn: &Connection) -> Result<usize> {
     conn.execute(
         "CREATE TABLE IF NOT EXISTS rss (
   id INTEGER PRIMARY KEY NOT NULL,
@@ -16,15 +164,346 @@ pub fn init() -> Result<usize> {
   latest_link TEXT NOT NULL)",
         params![],
     )
+    .and(add_column(
+        conn,
+        "rss",
+        "summary_len",
+        "INTEGER NOT NULL DEFAULT 0",
+    ))
+    .and(add_column(
+        conn,
+        "rss",
+        "preview",
+        "INTEGER NOT NULL DEFAULT 0",
+    ))
+    .and(add_column(
+        conn,
+        "rss",
+        "updates",
+        "INTEGER NOT NULL DEFAULT 0",
+    ))
+    .and(add_health_columns(conn, "rss"))
+    .and(add_column(conn, "rss", "template", "TEXT"))
+    .and(add_pause_columns(conn, "rss"))
+    .and(add_column(conn, "rss", "canon", "TEXT"))
+    .and(fill_canon(conn, "rss", "feed", canonical_url))
+    .and(add_column(
+        conn,
+        "rss",
+        "latest_key",
+        "TEXT NOT NULL DEFAULT ''",
+    ))
+    .and(add_column(conn, "rss", "keywords", "TEXT"))
+    // one subscription per feed for the whole bot, not one per chat: a
+    // subscription has no chat, its route decides where the posts go
+    .and(conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS rss_canon ON rss (canon)",
+        params![],
+    ))
+    .and(conn.execute(
+        "CREATE TABLE IF NOT EXISTS rss_history (
+  id INTEGER PRIMARY KEY NOT NULL,
+  rss_id INTEGER NOT NULL,
+  time INTEGER NOT NULL,
+  field TEXT NOT NULL,
+  old TEXT NOT NULL,
+  new TEXT NOT NULL)",
+        params![],
+    ))
+    .and(conn.execute(
+        "CREATE TABLE IF NOT EXISTS rss_item (
+  rss_id INTEGER NOT NULL,
+  key TEXT NOT NULL,
+  title TEXT NOT NULL,
+  summary TEXT NOT NULL,
+  fingerprint TEXT NOT NULL,
+  updated INTEGER,
+  time INTEGER NOT NULL,
+  PRIMARY KEY (rss_id, key))",
+        params![],
+    ))
     .and(conn.execute(
+        "CREATE TABLE IF NOT EXISTS seen (
+  dest TEXT NOT NULL,
+  canon TEXT NOT NULL,
+  time INTEGER NOT NULL,
+  PRIMARY KEY (dest, canon))",
+        params![],
+    ))
+    .and(add_seen_dest(conn))
+}
+
+/// key the posts seen by where they went, those seen before went to every
+/// reader
+fn add_seen_dest(conn: &Connection) -> Result<usize> {
+    let exists = conn
+        .prepare("SELECT 1 FROM pragma_table_info('seen') WHERE name = 'dest'")?
+        .exists(params![])?;
+    if exists {
+        return Ok(0);
+    }
+    conn.execute_batch(
+        "ALTER TABLE seen RENAME TO seen_old;
+CREATE TABLE seen (
+  dest TEXT NOT NULL,
+  canon TEXT NOT NULL,
+  time INTEGER NOT NULL,
+  PRIMARY KEY (dest, canon));
+INSERT INTO seen (dest, canon, time) SELECT '', canon, time FROM seen_old;
+DROP TABLE seen_old;",
+    )?;
+    Ok(0)
+}
+
+pub fn init_repo(conn: &Connection) -> Result<usize> {
+    conn.execute(
         "CREATE TABLE IF NOT EXISTS repo (
   id INTEGER PRIMARY KEY NOT NULL,
   name TEXT NOT NULL,
   latest TEXT NOT NULL)",
         params![],
+    )
+    .and(add_health_columns(conn, "repo"))
+    .and(add_column(conn, "repo", "template", "TEXT"))
+    .and(add_pause_columns(conn, "repo"))
+    .and(add_column(conn, "repo", "canon", "TEXT"))
+    .and(fill_canon(conn, "repo", "name", canonical_repo))
+    // one per repo for the whole bot, like `rss_canon`
+    .and(conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS repo_canon ON repo (canon)",
+        params![],
+    ))
+}
+
+/// searches of github issues and pull requests, and the items they found
+pub fn init_watch(conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS watch (
+  id INTEGER PRIMARY KEY NOT NULL,
+  repo TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  query TEXT NOT NULL)",
+        params![],
+    )
+    .and(add_health_columns(conn, "watch"))
+    .and(add_pause_columns(conn, "watch"))
+    .and(add_column(
+        conn,
+        "watch",
+        "added",
+        "INTEGER NOT NULL DEFAULT 0",
+    ))
+    .and(conn.execute(
+        "CREATE TABLE IF NOT EXISTS watch_seen (
+  watch_id INTEGER NOT NULL,
+  number INTEGER NOT NULL,
+  PRIMARY KEY (watch_id, number))",
+        params![],
     ))
 }
 
+pub fn init_page(conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS page (
+  id INTEGER PRIMARY KEY NOT NULL,
+  url TEXT NOT NULL,
+  title TEXT NOT NULL,
+  item_sel TEXT NOT NULL,
+  title_sel TEXT NOT NULL,
+  link_sel TEXT NOT NULL,
+  latest TEXT NOT NULL)",
+        params![],
+    )
+    .and(add_health_columns(conn, "page"))
+}
+
+/// reminders and the timezones of the chats they are in
+pub fn init_remind(conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS reminder (
+  id INTEGER PRIMARY KEY NOT NULL,
+  cid TEXT NOT NULL,
+  thread INTEGER,
+  text TEXT NOT NULL,
+  next INTEGER NOT NULL,
+  rule TEXT)",
+        params![],
+    )
+    .and(conn.execute(
+        "CREATE TABLE IF NOT EXISTS chat_tz (
+  cid TEXT PRIMARY KEY NOT NULL,
+  tz TEXT NOT NULL)",
+        params![],
+    ))
+}
+
+/// how polling a subscription went lately, times are unix timestamps
+#[derive(Default)]
+pub struct Health {
+    pub last_attempt: Option<i64>,
+    pub last_success: Option<i64>,
+    pub last_status: Option<u16>,
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// paused subscriptions are not polled
+    pub paused: bool,
+}
+
+impl TryFrom<&Row<'_>> for Health {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            last_attempt: row.get("last_attempt")?,
+            last_success: row.get("last_success")?,
+            last_status: row.get("last_status")?,
+            failures: row.get("failures")?,
+            last_error: row.get("last_error")?,
+            paused: row.get("paused")?,
+        })
+    }
+}
+
+const HEALTH_COLUMNS: &str =
+    "last_attempt, last_success, last_status, failures, last_error, paused";
+
+/// a pause a user asked for, unlike `Health::paused` after failures
+#[derive(Default)]
+pub struct Pause {
+    /// when it was paused, `None` when it runs
+    pub since: Option<i64>,
+    /// when a snooze ends, `None` until it is resumed
+    pub until: Option<i64>,
+}
+
+impl Pause {
+    /// whether it should not be polled now
+    pub fn held(&self) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        self.since.is_some() && self.until.is_none_or(|until| until > now)
+    }
+}
+
+impl TryFrom<&Row<'_>> for Pause {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            since: row.get("pause_since")?,
+            until: row.get("pause_until")?,
+        })
+    }
+}
+
+const PAUSE_COLUMNS: &str = "pause_since, pause_until";
+
+/// the pause of a row in `table`, `None` if there is no such row
+pub fn get_pause(conn: &Connection, table: &str, id: i32) -> Result<Option<Pause>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} from {} where id = ?1",
+        PAUSE_COLUMNS, table
+    ))?;
+    let mut rows = stmt.query(params![id])?;
+    rows.next()?.map(Pause::try_from).transpose()
+}
+
+/// pause a row in `table` until `until`, or resume it with `None` for both
+pub fn set_pause(
+    conn: &Connection,
+    table: &str,
+    id: i32,
+    since: Option<i64>,
+    until: Option<i64>,
+) -> Result<usize> {
+    conn.execute(
+        &format!(
+            "UPDATE {} set pause_since = ?1, pause_until = ?2 where id = ?3",
+            table
+        ),
+        params![since, until, id],
+    )
+}
+
+/// resume the rows in `table` whose snooze is over
+pub fn end_snoozes(conn: &Connection, table: &str) -> Result<usize> {
+    conn.execute(
+        &format!(
+            "UPDATE {} set pause_since = NULL, pause_until = NULL where pause_until <= ?1",
+            table
+        ),
+        params![chrono::Utc::now().timestamp()],
+    )
+}
+
+/// record a successful poll of a row in `table`
+pub fn record_success(
+    conn: &Connection,
+    table: &str,
+    id: i32,
+    status: Option<u16>,
+) -> Result<usize> {
+    conn.execute(
+        &format!(
+            "UPDATE {} set last_attempt = ?1, last_success = ?1, last_status = ?2,
+  failures = 0, last_error = NULL where id = ?3",
+            table
+        ),
+        params![chrono::Utc::now().timestamp(), status, id],
+    )
+}
+
+/// record a failed poll of a row in `table`, pausing it after `max_failures`
+/// in a row; returns the number of failures in a row, and whether this one
+/// paused it
+pub fn record_failure(
+    conn: &Connection,
+    table: &str,
+    id: i32,
+    status: Option<u16>,
+    error: &str,
+    max_failures: u32,
+) -> Result<(u32, bool)> {
+    let select = format!("SELECT failures, paused from {} where id = ?1", table);
+    let (_, was_paused): (u32, bool) =
+        conn.query_row(&select, params![id], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    conn.execute(
+        &format!(
+            "UPDATE {} set last_attempt = ?1, last_status = ?2, last_error = ?3,
+  failures = failures + 1, paused = paused OR failures + 1 >= ?4 where id = ?5",
+            table
+        ),
+        params![
+            chrono::Utc::now().timestamp(),
+            status,
+            error,
+            max_failures,
+            id
+        ],
+    )?;
+    let (failures, paused): (u32, bool) =
+        conn.query_row(&select, params![id], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    Ok((failures, paused && !was_paused))
+}
+
+/// the health of a row in `table`, `None` if there is no such row
+pub fn get_health(conn: &Connection, table: &str, id: i32) -> Result<Option<Health>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} from {} where id = ?1",
+        HEALTH_COLUMNS, table
+    ))?;
+    let mut rows = stmt.query(params![id])?;
+    rows.next()?.map(Health::try_from).transpose()
+}
+
+/// start polling a row in `table` again with a clean record
+pub fn reset_health(conn: &Connection, table: &str, id: i32) -> Result<usize> {
+    conn.execute(
+        &format!(
+            "UPDATE {} set failures = 0, paused = 0 where id = ?1",
+            table
+        ),
+        params![id],
+    )
+}
+
 pub struct Rss {
     pub id: i32,
     pub home: String,
@@ -32,6 +511,28 @@ pub struct Rss {
     pub feed: String,
     pub latest_title: String,
     pub latest_link: String,
+    /// what tells the latest entry apart, its id or else its link; empty
+    /// for feeds subscribed before it was kept
+    pub latest_key: String,
+    /// include a summary of at most this many chars, 0 for title links only
+    pub summary_len: usize,
+    pub preview: bool,
+    pub updates: Updates,
+    /// how a post looks, see `template::Template`, `None` for the default
+    pub template: Option<String>,
+    /// words a post must have, or with a `-` must not, `None` for every post
+    pub keywords: Option<String>,
+    pub pause: Pause,
+    pub health: Health,
+}
+
+/// whether to tell when an already notified entry changes
+#[derive(Clone, Copy, PartialEq)]
+pub enum Updates {
+    Off,
+    On,
+    /// also show what changed
+    Diff,
 }
 
 impl TryFrom<&Row<'_>> for Rss {
@@ -44,18 +545,42 @@ impl TryFrom<&Row<'_>> for Rss {
             feed: row.get("feed")?,
             latest_title: row.get("latest_title")?,
             latest_link: row.get("latest_link")?,
+            latest_key: row.get("latest_key")?,
+            summary_len: row.get("summary_len")?,
+            preview: row.get("preview")?,
+            updates: match row.get::<_, u8>("updates")? {
+                1 => Updates::On,
+                2 => Updates::Diff,
+                _ => Updates::Off,
+            },
+            template: row.get("template")?,
+            keywords: row.get("keywords")?,
+            pause: Pause::try_from(row)?,
+            health: Health::try_from(row)?,
         })
     }
 }
 
 pub fn list_rss(conn: &Connection) -> Result<Vec<Rss>> {
-    let mut stmt = conn.prepare(
-        "SELECT id, home, title, feed, latest_title, latest_link from rss order by id asc",
-    )?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, home, title, feed, latest_title, latest_link, latest_key, summary_len, preview,
+  updates, template, keywords, {}, {} from rss order by id asc",
+        PAUSE_COLUMNS, HEALTH_COLUMNS
+    ))?;
     let res = stmt.query_map(rusqlite::params![], |r| Rss::try_from(r))?;
     res.into_iter().collect()
 }
 
+pub fn get_rss(conn: &Connection, id: i32) -> Result<Option<Rss>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, home, title, feed, latest_title, latest_link, latest_key, summary_len, preview,
+  updates, template, keywords, {}, {} from rss where id = ?1",
+        PAUSE_COLUMNS, HEALTH_COLUMNS
+    ))?;
+    let mut rows = stmt.query(params![id])?;
+    rows.next()?.map(Rss::try_from).transpose()
+}
+
 pub fn insert_rss(
     conn: &Connection,
     home: &str,
@@ -63,13 +588,37 @@ pub fn insert_rss(
     feed: &str,
     latest_title: &str,
     latest_link: &str,
+    latest_key: &str,
 ) -> Result<usize> {
     conn.execute(
-        "INSERT INTO rss (home, title, feed, latest_title, latest_link) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![home, title, feed, latest_title, latest_link])
+        "INSERT INTO rss (home, title, feed, latest_title, latest_link, latest_key, canon)
+  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            home,
+            title,
+            feed,
+            latest_title,
+            latest_link,
+            latest_key,
+            canonical_url(feed)
+        ],
+    )
+}
+
+/// the id of the feed whose canonical url is `canon`
+pub fn find_rss(conn: &Connection, canon: &str) -> Result<Option<i32>> {
+    let mut stmt = conn.prepare("SELECT id from rss where canon = ?1")?;
+    let mut rows = stmt.query(params![canon])?;
+    rows.next()?.map(|r| r.get(0)).transpose()
 }
 
 pub fn delete_rss(conn: &Connection, id_to_del: i32) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM rss_history where rss_id = ?1",
+        params![id_to_del],
+    )?;
+    conn.execute("DELETE FROM rss_item where rss_id = ?1", params![id_to_del])?;
+    delete_route(conn, "rss", id_to_del)?;
     conn.execute("DELETE FROM rss where id = ?1", params![id_to_del])
 }
 
@@ -78,10 +627,152 @@ pub fn update_rss(
     id: i32,
     latest_title: &str,
     latest_link: &str,
+    latest_key: &str,
 ) -> Result<usize> {
     conn.execute(
-        "UPDATE rss set latest_title = ?1, latest_link = ?2 where id = ?3",
-        params![latest_title, latest_link, id],
+        "UPDATE rss set latest_title = ?1, latest_link = ?2, latest_key = ?3 where id = ?4",
+        params![latest_title, latest_link, latest_key, id],
+    )
+}
+
+/// change a stored field of a feed, and keep the old value in its history
+pub fn change_rss(conn: &Connection, id: i32, field: &str, old: &str, new: &str) -> Result<usize> {
+    let column = match field {
+        "feed" => "feed",
+        "title" => "title",
+        "home" => "home",
+        _ => return Err(rusqlite::Error::InvalidColumnName(field.to_owned())),
+    };
+    conn.execute(
+        &format!("UPDATE rss set {} = ?1 where id = ?2", column),
+        params![new, id],
+    )?;
+    if field == "feed" {
+        conn.execute(
+            "UPDATE rss set canon = ?1 where id = ?2",
+            params![canonical_url(new), id],
+        )?;
+    }
+    conn.execute(
+        "INSERT INTO rss_history (rss_id, time, field, old, new) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![id, chrono::Utc::now().timestamp(), field, old, new],
+    )
+}
+
+pub struct RssHistory {
+    pub time: i64,
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl TryFrom<&Row<'_>> for RssHistory {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            time: row.get("time")?,
+            field: row.get("field")?,
+            old: row.get("old")?,
+            new: row.get("new")?,
+        })
+    }
+}
+
+pub fn list_rss_history(conn: &Connection, id: i32) -> Result<Vec<RssHistory>> {
+    let mut stmt = conn.prepare(
+        "SELECT time, field, old, new from rss_history where rss_id = ?1 order by id asc",
+    )?;
+    let res = stmt.query_map(params![id], |r| RssHistory::try_from(r))?;
+    res.into_iter().collect()
+}
+
+pub fn set_rss_summary(conn: &Connection, id: i32, summary_len: usize) -> Result<usize> {
+    conn.execute(
+        "UPDATE rss set summary_len = ?1 where id = ?2",
+        params![summary_len, id],
+    )
+}
+
+pub fn set_rss_preview(conn: &Connection, id: i32, preview: bool) -> Result<usize> {
+    conn.execute(
+        "UPDATE rss set preview = ?1 where id = ?2",
+        params![preview, id],
+    )
+}
+
+pub fn set_rss_template(conn: &Connection, id: i32, template: Option<&str>) -> Result<usize> {
+    conn.execute(
+        "UPDATE rss set template = ?1 where id = ?2",
+        params![template, id],
+    )
+}
+
+pub fn set_rss_keywords(conn: &Connection, id: i32, keywords: Option<&str>) -> Result<usize> {
+    conn.execute(
+        "UPDATE rss set keywords = ?1 where id = ?2",
+        params![keywords, id],
+    )
+}
+
+pub fn set_rss_updates(conn: &Connection, id: i32, updates: Updates) -> Result<usize> {
+    conn.execute(
+        "UPDATE rss set updates = ?1 where id = ?2",
+        params![updates as u8, id],
+    )
+}
+
+/// what an entry of a feed looked like when it was last seen
+pub struct RssItem {
+    pub title: String,
+    pub summary: String,
+    pub fingerprint: String,
+    /// the `updated` date of the entry, a unix timestamp
+    pub updated: Option<i64>,
+}
+
+impl TryFrom<&Row<'_>> for RssItem {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            title: row.get("title")?,
+            summary: row.get("summary")?,
+            fingerprint: row.get("fingerprint")?,
+            updated: row.get("updated")?,
+        })
+    }
+}
+
+pub fn get_rss_item(conn: &Connection, rss_id: i32, key: &str) -> Result<Option<RssItem>> {
+    let mut stmt = conn.prepare(
+        "SELECT title, summary, fingerprint, updated from rss_item where rss_id = ?1 and key = ?2",
+    )?;
+    let mut rows = stmt.query(params![rss_id, key])?;
+    rows.next()?.map(RssItem::try_from).transpose()
+}
+
+pub fn put_rss_item(conn: &Connection, rss_id: i32, key: &str, item: &RssItem) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO rss_item (rss_id, key, title, summary, fingerprint, updated, time)
+  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+  ON CONFLICT(rss_id, key) DO UPDATE SET title = excluded.title, summary = excluded.summary,
+  fingerprint = excluded.fingerprint, updated = excluded.updated, time = excluded.time",
+        params![
+            rss_id,
+            key,
+            item.title,
+            item.summary,
+            item.fingerprint,
+            item.updated,
+            chrono::Utc::now().timestamp()
+        ],
+    )
+}
+
+/// forget the entries that have not been in the feed since `before`
+pub fn prune_rss_items(conn: &Connection, rss_id: i32, before: i64) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM rss_item where rss_id = ?1 and time < ?2",
+        params![rss_id, before],
     )
 }
 
@@ -89,6 +780,10 @@ pub struct Repo {
     pub id: i32,
     pub name: String,
     pub latest: String,
+    /// how a release looks, `None` for the default
+    pub template: Option<String>,
+    pub pause: Pause,
+    pub health: Health,
 }
 
 impl TryFrom<&Row<'_>> for Repo {
@@ -98,30 +793,553 @@ impl TryFrom<&Row<'_>> for Repo {
             id: row.get("id")?,
             name: row.get("name")?,
             latest: row.get("latest")?,
+            template: row.get("template")?,
+            pause: Pause::try_from(row)?,
+            health: Health::try_from(row)?,
         })
     }
 }
 
 pub fn list_repo(conn: &Connection) -> Result<Vec<Repo>> {
-    let mut stmt = conn.prepare("SELECT id, name, latest from repo order by id asc")?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, name, latest, template, {}, {} from repo order by id asc",
+        PAUSE_COLUMNS, HEALTH_COLUMNS
+    ))?;
     let res = stmt.query_map(rusqlite::params![], |r| Repo::try_from(r))?;
     res.into_iter().collect()
 }
 
+pub fn get_repo(conn: &Connection, id: i32) -> Result<Option<Repo>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, name, latest, template, {}, {} from repo where id = ?1",
+        PAUSE_COLUMNS, HEALTH_COLUMNS
+    ))?;
+    let mut rows = stmt.query(params![id])?;
+    rows.next()?.map(Repo::try_from).transpose()
+}
+
 pub fn insert_repo(conn: &Connection, name: &str, latest: &str) -> Result<usize> {
     conn.execute(
-        "INSERT INTO repo (name, latest) VALUES (?1, ?2)",
-        params![name, latest],
+        "INSERT INTO repo (name, latest, canon) VALUES (?1, ?2, ?3)",
+        params![name, latest, canonical_repo(name)],
     )
 }
 
+/// the id of the repo whose canonical name is `canon`
+pub fn find_repo(conn: &Connection, canon: &str) -> Result<Option<i32>> {
+    let mut stmt = conn.prepare("SELECT id from repo where canon = ?1")?;
+    let mut rows = stmt.query(params![canon])?;
+    rows.next()?.map(|r| r.get(0)).transpose()
+}
+
 pub fn delete_repo(conn: &Connection, id_to_del: i32) -> Result<usize> {
+    delete_route(conn, "repo", id_to_del)?;
     conn.execute("DELETE FROM repo where id = ?1", params![id_to_del])
 }
 
+pub fn set_repo_template(conn: &Connection, id: i32, template: Option<&str>) -> Result<usize> {
+    conn.execute(
+        "UPDATE repo set template = ?1 where id = ?2",
+        params![template, id],
+    )
+}
+
 pub fn update_repo(conn: &Connection, id: i32, latest: &str) -> Result<usize> {
     conn.execute(
-        "UPDATE repo set latest = ?1 where id = ?3",
+        "UPDATE repo set latest = ?1 where id = ?2",
+        params![latest, id],
+    )
+}
+
+/// a github search for new issues or pull requests of a repo
+pub struct Watch {
+    pub id: i32,
+    /// like owner/name
+    pub repo: String,
+    /// issues or prs
+    pub kind: String,
+    /// search qualifiers like `label:security`, may be empty
+    pub query: String,
+    /// when it was added, what happened before is not told
+    pub added: i64,
+    pub pause: Pause,
+    pub health: Health,
+}
+
+impl TryFrom<&Row<'_>> for Watch {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            repo: row.get("repo")?,
+            kind: row.get("kind")?,
+            query: row.get("query")?,
+            added: row.get("added")?,
+            pause: Pause::try_from(row)?,
+            health: Health::try_from(row)?,
+        })
+    }
+}
+
+pub fn list_watch(conn: &Connection) -> Result<Vec<Watch>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, repo, kind, query, added, {}, {} from watch order by id asc",
+        PAUSE_COLUMNS, HEALTH_COLUMNS
+    ))?;
+    let res = stmt.query_map(rusqlite::params![], |r| Watch::try_from(r))?;
+    res.into_iter().collect()
+}
+
+pub fn get_watch(conn: &Connection, id: i32) -> Result<Option<Watch>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, repo, kind, query, added, {}, {} from watch where id = ?1",
+        PAUSE_COLUMNS, HEALTH_COLUMNS
+    ))?;
+    let mut rows = stmt.query(params![id])?;
+    rows.next()?.map(Watch::try_from).transpose()
+}
+
+/// the id of a watch with the same search
+pub fn find_watch(conn: &Connection, repo: &str, kind: &str, query: &str) -> Result<Option<i32>> {
+    let mut stmt =
+        conn.prepare("SELECT id from watch where repo = ?1 and kind = ?2 and query = ?3")?;
+    let mut rows = stmt.query(params![repo, kind, query])?;
+    rows.next()?.map(|r| r.get(0)).transpose()
+}
+
+pub fn insert_watch(conn: &Connection, repo: &str, kind: &str, query: &str) -> Result<i32> {
+    conn.execute(
+        "INSERT INTO watch (repo, kind, query, added) VALUES (?1, ?2, ?3, ?4)",
+        params![repo, kind, query, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid() as i32)
+}
+
+pub fn delete_watch(conn: &Connection, id_to_del: i32) -> Result<usize> {
+    delete_route(conn, "watch", id_to_del)?;
+    conn.execute(
+        "DELETE FROM watch_seen where watch_id = ?1",
+        params![id_to_del],
+    )?;
+    conn.execute("DELETE FROM watch where id = ?1", params![id_to_del])
+}
+
+/// whether a watch has found the issue or pull request `number` before
+pub fn watch_seen(conn: &Connection, watch_id: i32, number: i64) -> Result<bool> {
+    conn.prepare("SELECT 1 from watch_seen where watch_id = ?1 and number = ?2")?
+        .exists(params![watch_id, number])
+}
+
+pub fn insert_watch_seen(conn: &Connection, watch_id: i32, number: i64) -> Result<usize> {
+    conn.execute(
+        "INSERT OR IGNORE INTO watch_seen (watch_id, number) VALUES (?1, ?2)",
+        params![watch_id, number],
+    )
+}
+
+/// a web page watched like a feed, either as items picked by css selectors
+/// or, without an item selector, as its whole text
+pub struct Page {
+    pub id: i32,
+    pub url: String,
+    pub title: String,
+    pub item_sel: String,
+    pub title_sel: String,
+    pub link_sel: String,
+    /// the links seen last time as json, or the text of the whole page
+    pub latest: String,
+    pub health: Health,
+}
+
+impl TryFrom<&Row<'_>> for Page {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            url: row.get("url")?,
+            title: row.get("title")?,
+            item_sel: row.get("item_sel")?,
+            title_sel: row.get("title_sel")?,
+            link_sel: row.get("link_sel")?,
+            latest: row.get("latest")?,
+            health: Health::try_from(row)?,
+        })
+    }
+}
+
+pub fn list_page(conn: &Connection) -> Result<Vec<Page>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, url, title, item_sel, title_sel, link_sel, latest, {} from page order by id asc",
+        HEALTH_COLUMNS
+    ))?;
+    let res = stmt.query_map(rusqlite::params![], |r| Page::try_from(r))?;
+    res.into_iter().collect()
+}
+
+pub fn get_page(conn: &Connection, id: i32) -> Result<Option<Page>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, url, title, item_sel, title_sel, link_sel, latest, {} from page where id = ?1",
+        HEALTH_COLUMNS
+    ))?;
+    let mut rows = stmt.query(params![id])?;
+    rows.next()?.map(Page::try_from).transpose()
+}
+
+pub fn insert_page(
+    conn: &Connection,
+    url: &str,
+    title: &str,
+    selectors: (&str, &str, &str),
+    latest: &str,
+) -> Result<usize> {
+    let (item_sel, title_sel, link_sel) = selectors;
+    conn.execute(
+        "INSERT INTO page (url, title, item_sel, title_sel, link_sel, latest)
+  VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![url, title, item_sel, title_sel, link_sel, latest],
+    )
+}
+
+pub fn delete_page(conn: &Connection, id_to_del: i32) -> Result<usize> {
+    delete_route(conn, "page", id_to_del)?;
+    conn.execute("DELETE FROM page where id = ?1", params![id_to_del])
+}
+
+pub fn update_page(conn: &Connection, id: i32, latest: &str) -> Result<usize> {
+    conn.execute(
+        "UPDATE page set latest = ?1 where id = ?2",
         params![latest, id],
     )
 }
+
+/// an item the bot has delivered, kept to be served as a feed
+pub struct Entry {
+    pub id: i64,
+    /// rss, repo, page or watch
+    pub kind: String,
+    /// the title of the subscription it came from
+    pub source: String,
+    pub title: String,
+    pub link: String,
+    pub summary: String,
+    pub time: i64,
+}
+
+impl TryFrom<&Row<'_>> for Entry {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            kind: row.get("kind")?,
+            source: row.get("source")?,
+            title: row.get("title")?,
+            link: row.get("link")?,
+            summary: row.get("summary")?,
+            time: row.get("time")?,
+        })
+    }
+}
+
+/// how many delivered items to keep
+const ENTRY_LIMIT: i64 = 1000;
+
+pub fn insert_entry(
+    conn: &Connection,
+    kind: &str,
+    sub_id: i32,
+    source: &str,
+    title: &str,
+    link: &str,
+    summary: &str,
+) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO entry (kind, sub_id, source, title, link, summary, time)
+  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            kind,
+            sub_id,
+            source,
+            title,
+            link,
+            summary,
+            chrono::Utc::now().timestamp()
+        ],
+    )
+    .and(conn.execute(
+        "DELETE FROM entry where id <= (SELECT max(id) from entry) - ?1",
+        params![ENTRY_LIMIT],
+    ))
+}
+
+/// the latest delivered items, of one kind or one subscription if given
+pub fn list_entry(
+    conn: &Connection,
+    kind: Option<&str>,
+    sub_id: Option<i32>,
+    limit: usize,
+) -> Result<Vec<Entry>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, kind, source, title, link, summary, time from entry
+  where (?1 IS NULL OR kind = ?1) and (?2 IS NULL OR sub_id = ?2)
+  order by id desc limit ?3",
+    )?;
+    let res = stmt.query_map(params![kind, sub_id, limit], |r| Entry::try_from(r))?;
+    res.into_iter().collect()
+}
+
+/// whether a post with the canonical link was delivered to `dest` since
+/// the time, see `route::destination`
+pub fn seen_since(conn: &Connection, dest: &str, canon: &str, since: i64) -> Result<bool> {
+    conn.prepare("SELECT 1 from seen where dest = ?1 and canon = ?2 and time >= ?3")?
+        .exists(params![dest, canon, since])
+}
+
+pub fn mark_seen(conn: &Connection, dest: &str, canon: &str) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO seen (dest, canon, time) VALUES (?1, ?2, ?3)
+  ON CONFLICT(dest, canon) DO UPDATE SET time = excluded.time",
+        params![dest, canon, chrono::Utc::now().timestamp()],
+    )
+}
+
+pub fn prune_seen(conn: &Connection, before: i64) -> Result<usize> {
+    conn.execute("DELETE FROM seen where time < ?1", params![before])
+}
+
+/// a question a command asked a user in a chat, waiting for the answer
+#[derive(Clone, Debug)]
+pub struct Dialogue {
+    /// the command the answer goes to
+    pub cmd: String,
+    /// where the command is in the conversation
+    pub step: String,
+    /// whatever the command needs to remember until then
+    pub data: String,
+}
+
+impl TryFrom<&Row<'_>> for Dialogue {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            cmd: row.get("cmd")?,
+            step: row.get("step")?,
+            data: row.get("data")?,
+        })
+    }
+}
+
+/// the dialogue of a user in a chat, unless it expired
+pub fn get_dialogue(conn: &Connection, cid: &str, uid: &str) -> Result<Option<Dialogue>> {
+    let mut stmt = conn.prepare(
+        "SELECT cmd, step, data from dialogue where cid = ?1 and uid = ?2 and expires > ?3",
+    )?;
+    let mut rows = stmt.query(params![cid, uid, chrono::Utc::now().timestamp()])?;
+    rows.next()?.map(Dialogue::try_from).transpose()
+}
+
+pub fn set_dialogue(
+    conn: &Connection,
+    cid: &str,
+    uid: &str,
+    dialogue: &Dialogue,
+    expires: i64,
+) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO dialogue (cid, uid, cmd, step, data, expires)
+  VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            cid,
+            uid,
+            dialogue.cmd,
+            dialogue.step,
+            dialogue.data,
+            expires
+        ],
+    )
+}
+
+pub fn delete_dialogue(conn: &Connection, cid: &str, uid: &str) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM dialogue where cid = ?1 and uid = ?2",
+        params![cid, uid],
+    )
+}
+
+/// where the notifications of a subscription go instead of every reader
+pub struct Route {
+    pub chat: String,
+    /// the topic of a forum supergroup
+    pub thread: Option<i64>,
+}
+
+impl TryFrom<&Row<'_>> for Route {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            chat: row.get("chat")?,
+            thread: row.get("thread")?,
+        })
+    }
+}
+
+/// `kind` is the table of the subscription, like "rss"
+pub fn get_route(conn: &Connection, kind: &str, sub_id: i32) -> Result<Option<Route>> {
+    let mut stmt =
+        conn.prepare("SELECT chat, thread from route where kind = ?1 and sub_id = ?2")?;
+    let mut rows = stmt.query(params![kind, sub_id])?;
+    rows.next()?.map(Route::try_from).transpose()
+}
+
+pub fn set_route(conn: &Connection, kind: &str, sub_id: i32, route: &Route) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO route (kind, sub_id, chat, thread) VALUES (?1, ?2, ?3, ?4)",
+        params![kind, sub_id, route.chat, route.thread],
+    )
+}
+
+pub fn delete_route(conn: &Connection, kind: &str, sub_id: i32) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM route where kind = ?1 and sub_id = ?2",
+        params![kind, sub_id],
+    )
+}
+
+pub struct Reminder {
+    pub id: i64,
+    pub cid: String,
+    /// the topic of a forum supergroup
+    pub thread: Option<i64>,
+    pub text: String,
+    /// when it fires next, a unix timestamp
+    pub next: i64,
+    /// when it repeats, like "weekday 10:00", `None` for once
+    pub rule: Option<String>,
+}
+
+impl TryFrom<&Row<'_>> for Reminder {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            cid: row.get("cid")?,
+            thread: row.get("thread")?,
+            text: row.get("text")?,
+            next: row.get("next")?,
+            rule: row.get("rule")?,
+        })
+    }
+}
+
+/// the id of the new reminder
+pub fn insert_reminder(
+    conn: &Connection,
+    cid: &str,
+    thread: Option<i64>,
+    text: &str,
+    next: i64,
+    rule: Option<&str>,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO reminder (cid, thread, text, next, rule) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![cid, thread, text, next, rule],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// the reminders of a chat, the next one first
+pub fn list_reminders(conn: &Connection, cid: &str) -> Result<Vec<Reminder>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, cid, thread, text, next, rule from reminder where cid = ?1 order by next asc",
+    )?;
+    let res = stmt.query_map(params![cid], |r| Reminder::try_from(r))?;
+    res.into_iter().collect()
+}
+
+/// the reminders of every chat that should have fired by `now`
+pub fn due_reminders(conn: &Connection, now: i64) -> Result<Vec<Reminder>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, cid, thread, text, next, rule from reminder where next <= ?1 order by next asc",
+    )?;
+    let res = stmt.query_map(params![now], |r| Reminder::try_from(r))?;
+    res.into_iter().collect()
+}
+
+pub fn set_reminder_next(conn: &Connection, id: i64, next: i64) -> Result<usize> {
+    conn.execute(
+        "UPDATE reminder set next = ?1 where id = ?2",
+        params![next, id],
+    )
+}
+
+/// only a reminder of the chat, so that one chat can not cancel another's
+pub fn delete_reminder(conn: &Connection, cid: &str, id: i64) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM reminder where cid = ?1 and id = ?2",
+        params![cid, id],
+    )
+}
+
+pub fn get_chat_tz(conn: &Connection, cid: &str) -> Result<Option<String>> {
+    let mut stmt = conn.prepare("SELECT tz from chat_tz where cid = ?1")?;
+    let mut rows = stmt.query(params![cid])?;
+    rows.next()?.map(|r| r.get(0)).transpose()
+}
+
+pub fn set_chat_tz(conn: &Connection, cid: &str, tz: &str) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO chat_tz (cid, tz) VALUES (?1, ?2)",
+        params![cid, tz],
+    )
+}
+
+pub struct Acl {
+    pub user: String,
+    pub role: String,
+}
+
+impl TryFrom<&Row<'_>> for Acl {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row) -> Result<Self> {
+        Ok(Self {
+            user: row.get("user")?,
+            role: row.get("role")?,
+        })
+    }
+}
+
+pub fn list_acl(conn: &Connection) -> Result<Vec<Acl>> {
+    let mut stmt = conn.prepare("SELECT user, role from acl order by user asc")?;
+    let res = stmt.query_map(rusqlite::params![], |r| Acl::try_from(r))?;
+    res.into_iter().collect()
+}
+
+pub fn get_role(conn: &Connection, user: &str) -> Result<Option<String>> {
+    let mut stmt = conn.prepare("SELECT role from acl where user = ?1")?;
+    let mut rows = stmt.query(params![user])?;
+    rows.next()?.map(|r| r.get(0)).transpose()
+}
+
+pub fn set_role(conn: &Connection, user: &str, role: &str) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO acl (user, role) VALUES (?1, ?2)
+  ON CONFLICT(user) DO UPDATE SET role = excluded.role",
+        params![user, role],
+    )
+}
+
+/// make `user` the one owner, any other owner becomes an admin; returns how
+/// many were demoted
+pub fn set_owner(conn: &mut Connection, user: &str) -> Result<usize> {
+    let tx = conn.transaction()?;
+    let demoted = tx.execute(
+        "UPDATE acl set role = 'admin' where role = 'owner' and user != ?1",
+        params![user],
+    )?;
+    set_role(&tx, user, "owner")?;
+    tx.commit()?;
+    Ok(demoted)
+}
+
+pub fn delete_acl(conn: &Connection, user: &str) -> Result<usize> {
+    conn.execute("DELETE FROM acl where user = ?1", params![user])
+}
@@ -1,32 +1,346 @@
-use crate::utils::send;
+use crate::acl::Role;
+use crate::db::{delete_dialogue, get_conn, get_dialogue, set_dialogue, Dialogue};
+use crate::error::report;
+use crate::utils::{send_to, setting, Content, Target};
 use async_trait::async_trait;
+use log::info;
 use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// what a command does when the message that ran it is edited
+#[derive(Clone, Copy, PartialEq)]
+pub enum OnEdit {
+    /// run it again, if it was the latest command and the edit is recent
+    Rerun,
+    Ignore,
+}
 
 #[async_trait]
 pub trait Callback {
-    async fn callback(&self, cid: &str, msg: &str);
+    async fn callback(&self, ctx: &Context);
+
+    /// an edit is left alone, commands that only show things say `Rerun`
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Ignore
+    }
+}
+
+/// the message that a message replies to
+#[derive(Clone, Debug, Default)]
+pub struct Quoted {
+    pub message_id: i64,
+    pub uid: String,
+    pub text: String,
+}
+
+/// an incoming message, and how to answer it
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    pub cid: String,
+    /// the topic of a forum supergroup the message is in
+    pub thread: Option<i64>,
+    /// the sender, who may be one of many in a group
+    pub uid: String,
+    pub message_id: Option<i64>,
+    /// when the message was sent, a unix timestamp
+    pub date: i64,
+    /// whether this is an edit of a message seen before
+    pub edited: bool,
+    pub private: bool,
+    pub text: String,
+    /// the words after the command
+    pub args: Vec<String>,
+    pub reply_to: Option<Quoted>,
+    /// the command being run, set by the dispatcher
+    pub cmd: String,
+    /// the question of the command that this message answers
+    pub dialogue: Option<Dialogue>,
+}
+
+impl Context {
+    pub fn new(cid: &str, uid: &str, text: &str) -> Self {
+        Self {
+            cid: cid.to_owned(),
+            uid: uid.to_owned(),
+            text: text.to_owned(),
+            args: text.split_whitespace().skip(1).map(str::to_owned).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// the `n`th word after the command
+    pub fn arg(&self, n: usize) -> Option<&str> {
+        self.args.get(n).map(String::as_str)
+    }
+
+    /// the text after the command and `n` more words, with its lines
+    pub fn rest(&self, n: usize) -> &str {
+        let mut rest = self.text.trim_start();
+        for _ in 0..=n {
+            rest = rest
+                .trim_start_matches(|c: char| !c.is_whitespace())
+                .trim_start();
+        }
+        rest
+    }
+
+    /// where the command is in the conversation, `None` when it starts
+    pub fn step(&self) -> Option<&str> {
+        self.dialogue.as_ref().map(|d| d.step.as_str())
+    }
+
+    /// ask the user something: their next message in this chat goes to the
+    /// same command, with `step` and `data` in its `dialogue`
+    pub async fn ask(&self, question: Content, step: &str, data: &str) {
+        let dialogue = Dialogue {
+            cmd: self.cmd.clone(),
+            step: step.to_owned(),
+            data: data.to_owned(),
+        };
+        let expires = chrono::Utc::now().timestamp() + dialogue_timeout();
+        if let Err(e) = set_dialogue(&get_conn(), &self.cid, &self.uid, &dialogue, expires) {
+            self.reply(&report(e)).await;
+            return;
+        }
+        self.reply_content(question).await;
+    }
+
+    /// ask whether to go on with something that can not be undone, the
+    /// answer comes with the step "confirm", see `confirmed`
+    pub async fn ask_confirm(&self, question: &str, data: &str) {
+        let question = Content::Choice {
+            text: question.to_owned(),
+            buttons: vec![
+                ("yes".to_owned(), "yes".to_owned()),
+                ("no".to_owned(), "no".to_owned()),
+            ],
+        };
+        self.ask(question, "confirm", data).await;
+    }
+
+    /// the data of the confirmation that this message says yes to
+    pub fn confirmed(&self) -> Option<&str> {
+        self.dialogue
+            .as_ref()
+            .filter(|d| d.step == "confirm" && self.arg(0) == Some("yes"))
+            .map(|d| d.data.as_str())
+    }
+
+    pub async fn reply(&self, text: &str) {
+        let content = Content::Text {
+            text: text.to_owned(),
+            preview: false,
+        };
+        self.reply_content(content).await;
+    }
+
+    /// answer in the same chat, quoting the message in groups where it
+    /// would be unclear who is answered
+    pub async fn reply_content(&self, content: Content) {
+        let target = Target {
+            thread: self.thread,
+            reply_to: self.message_id.filter(|_| !self.private),
+            ..Target::chat(&self.cid)
+        };
+        send_to(target, content).await;
+    }
+}
+
+/// an incoming message on its way to a callback
+pub struct Request<'a> {
+    pub ctx: &'a Context,
+    pub cmd: &'a str,
+    /// the minimum role the command requires
+    pub role: Role,
+}
+
+/// a layer around every callback, it decides whether and how to call `next`
+#[async_trait]
+pub trait Middleware {
+    async fn handle(&self, req: &Request<'_>, next: Next<'_>);
+}
+
+/// the rest of the middleware chain, ending with the callback
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware + Sync>],
+    callback: &'a (dyn Callback + Sync),
+}
+
+impl Next<'_> {
+    pub async fn run(self, req: &Request<'_>) {
+        match self.middlewares.split_first() {
+            Some((first, rest)) => {
+                let next = Next {
+                    middlewares: rest,
+                    callback: self.callback,
+                };
+                first.handle(req, next).await;
+            }
+            None => self.callback.callback(req.ctx).await,
+        }
+    }
+}
+
+/// answers anything we do not understand
+struct Unknown {}
+
+#[async_trait]
+impl Callback for Unknown {
+    async fn callback(&self, ctx: &Context) {
+        ctx.reply("???").await;
+    }
+}
+
+/// ends the conversation the user is in
+struct Cancel {}
+
+#[async_trait]
+impl Callback for Cancel {
+    async fn callback(&self, ctx: &Context) {
+        match ctx.dialogue {
+            Some(_) => ctx.reply("cancelled").await,
+            None => ctx.reply("nothing to cancel").await,
+        }
+    }
+}
+
+/// how long after sending a command an edit may run it again,
+/// `EDIT_WINDOW` in seconds
+fn edit_window() -> i64 {
+    setting("EDIT_WINDOW", 120)
+}
+
+/// how long a question waits for its answer, `DIALOGUE_TIMEOUT` in seconds
+fn dialogue_timeout() -> i64 {
+    setting("DIALOGUE_TIMEOUT", 300)
 }
 
 #[derive(Default)]
 pub struct Dispatcher {
-    callbacks: HashMap<String, Box<dyn Callback + Sync>>,
+    callbacks: HashMap<String, (Role, Box<dyn Callback + Sync>)>,
+    middlewares: Vec<Box<dyn Middleware + Sync>>,
+    username: String,
+    /// the message id of the latest command of each user in each chat
+    latest: Mutex<HashMap<(String, String), i64>>,
 }
 
 impl Dispatcher {
-    pub fn register(&mut self, cmd: &str, callback: Box<dyn Callback + Sync>) {
-        self.callbacks.insert(cmd.to_owned(), callback);
+    pub fn register(&mut self, cmd: &str, role: Role, callback: Box<dyn Callback + Sync>) {
+        self.callbacks.insert(cmd.to_owned(), (role, callback));
+    }
+
+    /// add a middleware, the first one added is the outermost
+    pub fn wrap(&mut self, middleware: Box<dyn Middleware + Sync>) {
+        self.middlewares.push(middleware);
+    }
+
+    /// the bot's own username, used to recognize `/cmd@botname`
+    pub fn set_username(&mut self, username: &str) {
+        self.username = username.to_owned();
+    }
+
+    /// split `/cmd@botname` into the command and whether it is addressed to us
+    /// explicitly; `None` if the command is meant for another bot
+    fn parse_cmd<'a>(&self, token: &'a str) -> Option<(&'a str, bool)> {
+        match token.split_once('@') {
+            Some((cmd, bot)) if bot.eq_ignore_ascii_case(&self.username) => Some((cmd, true)),
+            Some(_) => None,
+            None => Some((token, false)),
+        }
+    }
+
+    /// find the callback for a message, `None` if we should stay silent
+    fn route<'a>(
+        &'a self,
+        msg: &'a str,
+        private: bool,
+    ) -> Option<(&'a str, Role, &'a (dyn Callback + Sync))> {
+        let unknown = &Unknown {} as &(dyn Callback + Sync);
+        let cancel = &Cancel {} as &(dyn Callback + Sync);
+        let Some(token) = msg.split_whitespace().next().filter(|t| t.starts_with('/')) else {
+            // in groups we see all kinds of chatting, only answer commands
+            return private.then_some(("", Role::Reader, unknown));
+        };
+        let (cmd, addressed) = self.parse_cmd(token)?;
+        match self.callbacks.get(cmd) {
+            Some((role, callback)) => Some((cmd, *role, callback.as_ref())),
+            None if cmd == "/cancel" => Some((cmd, Role::Reader, cancel)),
+            None => (private || addressed).then_some((cmd, Role::Reader, unknown)),
+        }
     }
 
-    pub async fn dispatch(&self, cid: &str, msg: &str) {
-        if let Some(cmd) = msg.split_whitespace().next() {
-            if let Some(callback) = self.callbacks.get(cmd) {
-                let callback = callback.as_ref() as &dyn Callback;
-                callback.callback(cid, msg).await;
-            } else {
-                send(cid, "???").await;
+    /// whether an edited command may run again
+    fn rerun(&self, ctx: &Context, callback: &(dyn Callback + Sync)) -> bool {
+        let key = (ctx.cid.clone(), ctx.uid.clone());
+        let latest = self.latest.lock().unwrap().get(&key).copied();
+        callback.on_edit() == OnEdit::Rerun
+            && ctx.message_id.is_some()
+            && ctx.message_id == latest
+            && chrono::Utc::now().timestamp() - ctx.date <= edit_window()
+    }
+
+    pub async fn dispatch(&self, ctx: &Context) {
+        let answer = !ctx.text.trim_start().starts_with('/');
+        if ctx.edited {
+            // an edit never answers a question, nor replays a command unless
+            // the command allows it
+            let rerun = match self.route(&ctx.text, ctx.private) {
+                Some((_, _, callback)) if !answer => self.rerun(ctx, callback),
+                _ => false,
+            };
+            if !rerun {
+                info!("ignore edit of message {:?}", ctx.message_id);
+                return;
+            }
+        } else if let (false, Some(message_id)) = (answer, ctx.message_id) {
+            let key = (ctx.cid.clone(), ctx.uid.clone());
+            self.latest.lock().unwrap().insert(key, message_id);
+        }
+        let conn = get_conn();
+        let dialogue = get_dialogue(&conn, &ctx.cid, &ctx.uid).unwrap_or_else(|e| {
+            report(e);
+            None
+        });
+        let mut ctx = ctx.clone();
+        let routed = match &dialogue {
+            // anything but a command answers the question
+            Some(d) if answer => {
+                ctx.args = ctx.text.split_whitespace().map(str::to_owned).collect();
+                self.callbacks
+                    .get_key_value(&d.cmd)
+                    .map(|(cmd, (role, callback))| (cmd.as_str(), *role, callback.as_ref()))
+            }
+            _ => self.route(&ctx.text, ctx.private),
+        };
+        let Some((cmd, role, callback)) = routed else {
+            // the command that asked is gone, so nothing can answer it
+            if dialogue.is_some() && answer {
+                if let Err(e) = delete_dialogue(&conn, &ctx.cid, &ctx.uid) {
+                    report(e);
+                }
+            }
+            return;
+        };
+        // a question is answered once, and left behind by another command
+        if let Some(d) = dialogue {
+            if let Err(e) = delete_dialogue(&conn, &ctx.cid, &ctx.uid) {
+                report(e);
+            }
+            if answer || d.cmd == cmd || cmd == "/cancel" {
+                ctx.dialogue = Some(d);
             }
-        } else {
-            send(cid, "???").await;
         }
+        drop(conn);
+        ctx.cmd = cmd.to_owned();
+        let req = Request {
+            ctx: &ctx,
+            cmd,
+            role,
+        };
+        let next = Next {
+            middlewares: &self.middlewares,
+            callback,
+        };
+        next.run(&req).await;
     }
 }
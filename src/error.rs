@@ -6,6 +6,112 @@ pub enum MyError {
     Request(#[from] reqwest::Error),
     #[error("url error: {0}")]
     Url(#[from] url::ParseError),
-    #[error("Custom error: {0}")]
-    Custom(String),
+    #[error("http status {status} from {url}")]
+    Status { url: String, status: u16 },
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    /// `sub` is the subscription it was about, like "rss 3", if any
+    #[error("db error{}: {source}", .sub.as_ref().map(|s| format!(" in {}", s)).unwrap_or_default())]
+    Db {
+        sub: Option<String>,
+        source: rusqlite::Error,
+    },
+    /// boxed, so the variant is there whichever features are built
+    #[error("can not parse feed {url}: {source}")]
+    Feed {
+        url: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("telegram error {code} in {method}: {description}")]
+    Telegram {
+        method: String,
+        code: i64,
+        description: String,
+    },
+    #[error("config error in {name}: {reason}")]
+    Config { name: String, reason: String },
+    /// `what` is the kind of thing, like "feed" or "repo", `key` its id or url
+    #[error("{what} {key} not found")]
+    NotFound { what: &'static str, key: String },
+    /// a template that does not parse, with why
+    #[error("bad template: {0}")]
+    Template(String),
+    /// a css selector of a page that does not parse
+    #[error("bad selector {selector}: {reason}")]
+    Selector { selector: String, reason: String },
+    #[error("too many redirects from {url}")]
+    Redirects { url: String },
+    /// the bot may not post in a chat it should send to
+    #[error("can not post in {chat}")]
+    CanNotPost { chat: String },
+    /// the bot is not running, or was built without a transport
+    #[error("no transport")]
+    NoTransport,
+}
+
+impl From<rusqlite::Error> for MyError {
+    fn from(source: rusqlite::Error) -> Self {
+        MyError::Db { sub: None, source }
+    }
+}
+
+impl MyError {
+    /// a database error about the subscription `id` in `table`
+    pub fn db(source: rusqlite::Error, table: &str, id: i32) -> Self {
+        MyError::Db {
+            sub: Some(format!("{} {}", table, id)),
+            source,
+        }
+    }
+
+    /// a short name of the kind of error, for logs and counting
+    pub fn label(&self) -> &'static str {
+        match self {
+            MyError::Request(e) if e.is_timeout() => "timeout",
+            MyError::Request(_) => "request",
+            MyError::Url(_) => "url",
+            MyError::Status { .. } => "status",
+            MyError::Io(_) => "io",
+            MyError::Db { .. } => "db",
+            MyError::Feed { .. } => "feed",
+            MyError::Telegram { .. } => "telegram",
+            MyError::Config { .. } => "config",
+            MyError::NotFound { .. } => "not_found",
+            MyError::Template(_) => "template",
+            MyError::Selector { .. } => "selector",
+            MyError::Redirects { .. } => "redirects",
+            MyError::CanNotPost { .. } => "can_not_post",
+            MyError::NoTransport => "no_transport",
+        }
+    }
+
+    /// what to tell the chat, without internals like sql or stack details
+    pub fn reply(&self) -> String {
+        match self {
+            MyError::Request(e) if e.is_timeout() => "the site took too long to answer".to_owned(),
+            MyError::Request(_) => "can not reach the site".to_owned(),
+            MyError::Url(e) => format!("bad url: {}", e),
+            MyError::Status { status, .. } => format!("the site answered with http {}", status),
+            MyError::Io(_) => "can not read the file".to_owned(),
+            MyError::Db { .. } => "database error, see the logs".to_owned(),
+            MyError::Feed { .. } => "not a feed that can be read".to_owned(),
+            MyError::Telegram { description, .. } => format!("telegram says: {}", description),
+            MyError::Config { name, .. } => format!("{} is not configured right", name),
+            MyError::NotFound { what, .. } => format!("{} not found", what),
+            MyError::Template(reason) => reason.clone(),
+            MyError::Selector { selector, reason } => {
+                format!("bad selector {}: {}", selector, reason)
+            }
+            MyError::Redirects { .. } => "the site redirects too often".to_owned(),
+            MyError::CanNotPost { chat } => format!("can not post in {}", chat),
+            MyError::NoTransport => "the bot is not connected".to_owned(),
+        }
+    }
+}
+
+/// log an error with its label, and give the reply for the chat
+pub fn report(e: impl Into<MyError>) -> String {
+    let e = e.into();
+    log::error!("[{}] {}", e.label(), e);
+    e.reply()
 }
@@ -0,0 +1,236 @@
+use crate::acl::Role;
+use crate::bot::Module;
+use crate::db::{
+    get_conn, get_page, get_repo, get_rss, get_watch, list_page, list_repo, list_rss, list_watch,
+    record_failure, record_success, Health,
+};
+use crate::dispatcher::{Callback, Context, Dispatcher, OnEdit};
+use crate::error::{report, MyError};
+use crate::route::alert_sub;
+use crate::utils::{escape_markdown, setting, timezone};
+use async_trait::async_trait;
+use log::error;
+use rusqlite::Connection;
+
+/// the kinds of subscriptions whose health is tracked
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Rss,
+    Repo,
+    Page,
+    Watch,
+}
+
+impl Kind {
+    pub fn table(&self) -> &'static str {
+        match self {
+            Kind::Rss => "rss",
+            Kind::Repo => "repo",
+            Kind::Page => "page",
+            Kind::Watch => "watch",
+        }
+    }
+
+    /// whether the module of this kind runs, see `bot::enabled`
+    pub fn enabled(&self) -> bool {
+        crate::bot::enabled(self.table())
+    }
+
+    /// whether there is a subscription of this kind with the id
+    pub fn exists(&self, id: i32) -> Result<bool, MyError> {
+        let conn = get_conn();
+        Ok(match self {
+            Kind::Rss => get_rss(&conn, id)?.is_some(),
+            Kind::Repo => get_repo(&conn, id)?.is_some(),
+            Kind::Page => get_page(&conn, id)?.is_some(),
+            Kind::Watch => get_watch(&conn, id)?.is_some(),
+        })
+    }
+
+    fn retry_cmd(&self) -> &'static str {
+        match self {
+            Kind::Rss => "/retry",
+            Kind::Repo => "/rretry",
+            Kind::Page => "/pretry",
+            Kind::Watch => "/wretry",
+        }
+    }
+}
+
+/// pause a subscription after this many failures in a row, from `MAX_FAILURES`
+fn max_failures() -> u32 {
+    setting("MAX_FAILURES", 10)
+}
+
+/// record how a poll went, and tell the chat when it gets the subscription paused
+pub async fn record(kind: Kind, id: i32, name: &str, res: Result<u16, &MyError>) {
+    let conn = get_conn();
+    let err = match res {
+        Ok(status) => {
+            if let Err(e) = record_success(&conn, kind.table(), id, Some(status)) {
+                report(MyError::db(e, kind.table(), id));
+            }
+            return;
+        }
+        Err(err) => err,
+    };
+    error!("[{}] {} {}: {}", err.label(), kind.table(), id, err);
+    let status = match err {
+        MyError::Status { status, .. } => Some(*status),
+        _ => None,
+    };
+    let max = max_failures();
+    match record_failure(&conn, kind.table(), id, status, &err.to_string(), max) {
+        // only the failure that pauses it, so there is a single alert
+        Ok((failures, true)) => {
+            let text = format!(
+                "paused {} {} \"{}\" after {} failures: {}",
+                kind.table(),
+                id,
+                escape_markdown(name),
+                failures,
+                escape_markdown(&err.to_string())
+            );
+            let retry = format!("{} {}", kind.retry_cmd(), id);
+            alert_sub(kind, id, text, vec![("retry".to_owned(), retry)]).await;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            report(MyError::db(e, kind.table(), id));
+        }
+    }
+}
+
+pub fn format_time(ts: Option<i64>) -> String {
+    ts.and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+        .map(|t| {
+            t.with_timezone(&timezone())
+                .format("%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|| "never".to_owned())
+}
+
+fn format_health(h: &Health) -> String {
+    if !h.paused && h.failures == 0 {
+        return format!("ok {}", format_time(h.last_success));
+    }
+    let status = h
+        .last_status
+        .map(|s| format!(" http {}", s))
+        .unwrap_or_default();
+    format!(
+        "{}{} failures, tried {}, ok {}{}: {}",
+        if h.paused { "paused, " } else { "" },
+        h.failures,
+        format_time(h.last_attempt),
+        format_time(h.last_success),
+        status,
+        escape_markdown(h.last_error.as_deref().unwrap_or_default())
+    )
+}
+
+/// the subscriptions of a kind, none when its module does not run
+fn subs<T>(
+    kind: Kind,
+    list: fn(&Connection) -> rusqlite::Result<Vec<T>>,
+) -> rusqlite::Result<Vec<T>> {
+    match kind.enabled() {
+        true => list(&get_conn()),
+        false => Ok(vec![]),
+    }
+}
+
+struct Status {}
+
+#[async_trait]
+impl Callback for Status {
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Rerun
+    }
+
+    async fn callback(&self, ctx: &Context) {
+        let all = subs(Kind::Rss, list_rss).and_then(|rs| {
+            Ok((
+                rs,
+                subs(Kind::Repo, list_repo)?,
+                subs(Kind::Page, list_page)?,
+                subs(Kind::Watch, list_watch)?,
+            ))
+        });
+        let (rs, repos, pages, watches) = match all {
+            Ok(all) => all,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let mut lines = rs
+            .iter()
+            .map(|r| {
+                let line = format!(
+                    "rss {} [{}]({}) {}",
+                    r.id,
+                    r.title,
+                    r.home,
+                    format_health(&r.health)
+                );
+                (&r.health, line)
+            })
+            .chain(repos.iter().map(|r| {
+                let line = format!(
+                    "repo {0} [{1}](https://github.com/{1}) {2}",
+                    r.id,
+                    r.name,
+                    format_health(&r.health)
+                );
+                (&r.health, line)
+            }))
+            .chain(pages.iter().map(|p| {
+                let line = format!(
+                    "page {} [{}]({}) {}",
+                    p.id,
+                    p.title,
+                    p.url,
+                    format_health(&p.health)
+                );
+                (&p.health, line)
+            }))
+            .chain(watches.iter().map(|w| {
+                let line = format!(
+                    "watch {0} [{1}](https://github.com/{1}) {2} {3}",
+                    w.id,
+                    w.repo,
+                    w.kind,
+                    format_health(&w.health)
+                );
+                (&w.health, line)
+            }))
+            .collect::<Vec<_>>();
+        // the unhealthy ones first, the worst on top
+        lines.sort_by_key(|(h, _)| std::cmp::Reverse((h.paused, h.failures)));
+        let reply = lines
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect::<Vec<_>>()
+            .join("\n");
+        if reply.is_empty() {
+            ctx.reply("no results").await;
+        } else {
+            ctx.reply(&reply).await;
+        }
+    }
+}
+
+/// `/status` of every subscription
+pub struct HealthModule {}
+
+impl Module for HealthModule {
+    fn name(&self) -> &'static str {
+        "health"
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        dispatcher.register("/status", Role::Reader, Box::new(Status {}));
+    }
+}
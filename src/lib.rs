@@ -0,0 +1,73 @@
+//! A telegram bot that follows feeds, github releases, issues and web pages.
+//!
+//! Put one together with [`Bot::builder`]: pick a transport like
+//! [`tg::Telegram`], the modules you want and any commands of your own.
+//! [`modules`] has every module of the build, cargo features like `rss` or
+//! `repo` decide which ones are built.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), turtlebot::MyError> {
+//! use turtlebot::{tg::Telegram, Bot};
+//!
+//! Bot::builder()
+//!     .transport(Telegram::new()?)
+//!     .database("bot.db")
+//!     .modules(turtlebot::modules())
+//!     .disable("web")
+//!     .run()
+//!     .await
+//! # }
+//! ```
+
+pub mod acl;
+pub mod bot;
+pub mod db;
+pub mod dispatcher;
+pub mod error;
+pub mod health;
+pub mod middleware;
+#[cfg(feature = "page")]
+pub mod page;
+pub mod pause;
+#[cfg(feature = "remind")]
+pub mod remind;
+#[cfg(feature = "repo")]
+pub mod repo;
+pub mod route;
+#[cfg(feature = "rss")]
+pub mod rss;
+pub mod template;
+pub mod tg;
+pub mod utils;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "web")]
+pub mod web;
+
+pub use acl::Role;
+pub use bot::{Bot, Builder, Module, Transport};
+pub use dispatcher::{Callback, Dispatcher, Middleware};
+pub use error::MyError;
+pub use utils::Content;
+
+/// every module of this build, see the cargo features for which ones
+pub fn modules() -> Vec<Box<dyn Module>> {
+    vec![
+        Box::new(acl::AclModule {}),
+        Box::new(health::HealthModule {}),
+        Box::new(pause::PauseModule {}),
+        Box::new(route::RouteModule {}),
+        #[cfg(feature = "rss")]
+        Box::new(rss::RssModule {}),
+        #[cfg(feature = "repo")]
+        Box::new(repo::RepoModule {}),
+        #[cfg(feature = "page")]
+        Box::new(page::PageModule {}),
+        #[cfg(feature = "remind")]
+        Box::new(remind::RemindModule {}),
+        #[cfg(feature = "watch")]
+        Box::new(watch::WatchModule {}),
+        #[cfg(feature = "web")]
+        Box::new(web::FeedServer {}),
+    ]
+}
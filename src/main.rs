@@ -1,37 +1,19 @@
-mod db;
-mod dispatcher;
-mod error;
-mod repo;
-mod rss;
-mod tg;
-mod utils;
-
 use log::{error, info};
-use tokio::select;
+use turtlebot::utils::{config, config_or};
+use turtlebot::{tg, Bot, MyError};
 
-async fn main_loop() {
-    let mut tg = tg::Telegram::new();
-    rss::register(&mut tg.dispatcher);
-    repo::register(&mut tg.dispatcher);
-    loop {
-        select! {
-            to_send = crate::utils::recv() => {
-                if let Some((id, msg)) = to_send {
-                    if let Err(err) = tg.send(id, msg).await {
-                        error!("tg send error: {}", err);
-                    }
-                } else {
-                    log::error!("channel recv error");
-                }
-            }
-            msg = tg.get() => {
-                match msg {
-                    Ok(j) => tg.process(j).await,
-                    Err(err) => error!("tg get error: {}", err),
-                }
-            }
-        }
+async fn run() -> Result<(), MyError> {
+    let mut bot = Bot::builder()
+        .transport(tg::Telegram::new()?)
+        .database(&config_or("DB_PATH", "data.db".to_owned())?)
+        .owner(&config::<String>("MASTER_ID")?)
+        .modules(turtlebot::modules());
+    // the modules of this build that should not run, like `repo,web`
+    let disabled: String = config_or("DISABLED_MODULES", String::new())?;
+    for name in disabled.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        bot = bot.disable(name);
     }
+    bot.run().await
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -39,8 +21,7 @@ async fn main() {
     dotenvy::dotenv().ok();
     env_logger::init();
     info!("start");
-    db::init().expect("init db");
-    tokio::spawn(rss::rss_monitor_loop());
-    tokio::spawn(repo::repo_monitor_loop());
-    main_loop().await;
+    if let Err(e) = run().await {
+        error!("[{}] {}", e.label(), e);
+    }
 }
@@ -0,0 +1,121 @@
+use crate::acl::{effective_role, Role};
+use crate::dispatcher::{Middleware, Next, Request};
+use async_trait::async_trait;
+use futures::FutureExt;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// only let users through whose role is enough for the command
+pub struct Auth {}
+
+#[async_trait]
+impl Middleware for Auth {
+    async fn handle(&self, req: &Request<'_>, next: Next<'_>) {
+        let role = effective_role(&req.ctx.cid, &req.ctx.uid);
+        if role.is_some_and(|r| r >= req.role) {
+            next.run(req).await;
+        } else if role.is_some_and(|r| r >= Role::Reader) {
+            req.ctx.reply("permission denied").await;
+        } else {
+            // strangers and blocked users get nothing
+            warn!("denied {} from user {}", req.cmd, req.ctx.uid);
+        }
+    }
+}
+
+/// at most `limit` messages per user in every `window`
+pub struct RateLimit {
+    limit: u32,
+    window: Duration,
+    users: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimit {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            users: Default::default(),
+        }
+    }
+
+    /// count one more message, returns how many were seen in the current window
+    fn hit(&self, uid: &str) -> u32 {
+        let now = Instant::now();
+        let mut users = self.users.lock().unwrap();
+        users.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        let (_, cnt) = users.entry(uid.to_owned()).or_insert((now, 0));
+        *cnt += 1;
+        *cnt
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimit {
+    async fn handle(&self, req: &Request<'_>, next: Next<'_>) {
+        let cnt = self.hit(&req.ctx.uid);
+        if cnt <= self.limit {
+            next.run(req).await;
+        } else if cnt == self.limit + 1 {
+            // tell once, then ignore silently until the window ends
+            warn!("rate limited user {}", req.ctx.uid);
+            req.ctx.reply("too many requests, slow down").await;
+        }
+    }
+}
+
+/// log how long every callback takes, and complain about slow ones
+pub struct Timing {
+    slow: Duration,
+}
+
+impl Timing {
+    pub fn new(slow: Duration) -> Self {
+        Self { slow }
+    }
+}
+
+#[async_trait]
+impl Middleware for Timing {
+    async fn handle(&self, req: &Request<'_>, next: Next<'_>) {
+        let start = Instant::now();
+        next.run(req).await;
+        let elapsed = start.elapsed();
+        if elapsed > self.slow {
+            warn!("{} took {:?}", req.cmd, elapsed);
+        } else {
+            info!("{} took {:?}", req.cmd, elapsed);
+        }
+    }
+}
+
+/// record who ran what, before anything else decides on it
+pub struct Audit {}
+
+#[async_trait]
+impl Middleware for Audit {
+    async fn handle(&self, req: &Request<'_>, next: Next<'_>) {
+        info!(target: "audit", "chat {} user {}: {}", req.ctx.cid, req.ctx.uid, req.ctx.text);
+        next.run(req).await;
+    }
+}
+
+/// keep the bot alive when a callback panics, and tell the user about it
+pub struct CatchPanic {}
+
+#[async_trait]
+impl Middleware for CatchPanic {
+    async fn handle(&self, req: &Request<'_>, next: Next<'_>) {
+        if AssertUnwindSafe(next.run(req))
+            .catch_unwind()
+            .await
+            .is_err()
+        {
+            error!("{} panicked", req.cmd);
+            req.ctx.reply("internal error").await;
+        }
+    }
+}
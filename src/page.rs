@@ -0,0 +1,589 @@
+use crate::acl::Role;
+use crate::bot::Module;
+use crate::db::{
+    self, delete_page, get_conn, get_page, insert_entry, insert_page, list_page, reset_health,
+    update_page, Page,
+};
+use crate::dispatcher::{Callback, Context, Dispatcher, OnEdit};
+use crate::error::{report, MyError};
+use crate::health::{record, Kind};
+use crate::route::notify_sub;
+use crate::utils::{config, escape_markdown, fetch, sleep, truncate, Content};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use log::info;
+use rusqlite::Connection;
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashSet;
+
+/// how many links to remember, so that items moving around are not new
+const SEEN_LIMIT: usize = 200;
+
+/// how many new lines of a changed page to show
+const DIFF_LINES: usize = 10;
+
+struct List {}
+
+#[async_trait]
+impl Callback for List {
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Rerun
+    }
+
+    async fn callback(&self, ctx: &Context) {
+        let conn = get_conn();
+        let ps = match list_page(&conn) {
+            Ok(ps) => ps,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let reply = ps
+            .into_iter()
+            .map(|p| {
+                let how = match p.item_sel.is_empty() {
+                    true => "whole page".to_owned(),
+                    false => escape_markdown(&p.item_sel),
+                };
+                format!("{} [{}]({}) {}", p.id, p.title, p.url, how)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if reply.is_empty() {
+            ctx.reply("no results").await;
+        } else {
+            ctx.reply(&reply).await;
+        }
+    }
+}
+
+fn parse_selector(sel: &str) -> Result<Option<Selector>, MyError> {
+    if sel.is_empty() {
+        return Ok(None);
+    }
+    Selector::parse(sel)
+        .map(Some)
+        .map_err(|e| MyError::Selector {
+            selector: sel.to_owned(),
+            reason: e.to_string(),
+        })
+}
+
+fn element_text(el: ElementRef) -> String {
+    el.text()
+        .flat_map(|t| t.split_whitespace())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// the items of a page as (title, link): the title is the text of the title
+/// selector or else of the item, the link is the href of the link selector
+/// or else of the item itself or its first link
+fn extract_items(
+    html: &str,
+    base: &url::Url,
+    item_sel: &str,
+    title_sel: &str,
+    link_sel: &str,
+) -> Result<Vec<(String, String)>, MyError> {
+    let item_sel = parse_selector(item_sel)?.ok_or(MyError::Selector {
+        selector: String::new(),
+        reason: "the item selector is empty".to_owned(),
+    })?;
+    let title_sel = parse_selector(title_sel)?;
+    let link_sel = parse_selector(link_sel)?.unwrap_or(Selector::parse("a[href]").unwrap());
+    let doc = Html::parse_document(html);
+    let items = doc
+        .select(&item_sel)
+        .filter_map(|item| {
+            let title = match &title_sel {
+                Some(sel) => item.select(sel).next().map(element_text)?,
+                None => element_text(item),
+            };
+            let href = item
+                .value()
+                .attr("href")
+                .or_else(|| item.select(&link_sel).next()?.value().attr("href"))?;
+            let link = base.join(href).ok()?.to_string();
+            Some((title, link))
+        })
+        .collect();
+    Ok(items)
+}
+
+/// the title of the page and its visible text, one line per text node
+fn page_text(html: &str) -> (Option<String>, String) {
+    let doc = Html::parse_document(html);
+    let title = Selector::parse("title")
+        .ok()
+        .and_then(|sel| doc.select(&sel).next().map(element_text))
+        .filter(|t| !t.is_empty());
+    let text = doc
+        .root_element()
+        .descendants()
+        .filter_map(|node| match node.value() {
+            Node::Text(text) => {
+                let parent = node.parent()?.value().as_element()?.name();
+                let hidden = matches!(
+                    parent,
+                    "script" | "style" | "noscript" | "template" | "title"
+                );
+                (!hidden).then_some(text)
+            }
+            _ => None,
+        })
+        .map(|t| t.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    (title, text)
+}
+
+/// the state to remember for a page: the links of its items, or its text
+fn snapshot(p: &Page, html: &str) -> Result<String, MyError> {
+    if p.item_sel.is_empty() {
+        return Ok(page_text(html).1);
+    }
+    let base = url::Url::parse(&p.url)?;
+    let items = extract_items(html, &base, &p.item_sel, &p.title_sel, &p.link_sel)?;
+    let links = items.into_iter().map(|(_, link)| link).collect::<Vec<_>>();
+    Ok(serde_json::to_string(&links).unwrap_or_default())
+}
+
+/// what is new on a page since it was last seen
+#[derive(Debug, Default, PartialEq)]
+struct Diff {
+    /// the lines added to a whole page
+    lines: Vec<String>,
+    /// the (title, link) of the new items
+    items: Vec<(String, String)>,
+    /// what to remember of the page now, `None` when nothing changed
+    latest: Option<String>,
+}
+
+/// compare a page as it is now with what `p.latest` remembers
+fn diff(p: &Page, html: &str) -> Result<Diff, MyError> {
+    if p.item_sel.is_empty() {
+        let (_, text) = page_text(html);
+        if text == p.latest {
+            return Ok(Diff::default());
+        }
+        let old = p.latest.lines().collect::<HashSet<_>>();
+        let lines = text
+            .lines()
+            .filter(|l| !old.contains(l))
+            .take(DIFF_LINES)
+            .map(|l| truncate(l, 200))
+            .collect();
+        return Ok(Diff {
+            lines,
+            latest: Some(text),
+            ..Default::default()
+        });
+    }
+
+    let base = url::Url::parse(&p.url)?;
+    let items = extract_items(html, &base, &p.item_sel, &p.title_sel, &p.link_sel)?;
+    if items.is_empty() {
+        return Err(MyError::NotFound {
+            what: "matching items",
+            key: p.url.clone(),
+        });
+    }
+    let mut seen: Vec<String> = serde_json::from_str(&p.latest).unwrap_or_default();
+    let new = items
+        .iter()
+        .filter(|(_, link)| !seen.contains(link))
+        .cloned()
+        .collect::<Vec<_>>();
+    if new.is_empty() {
+        return Ok(Diff::default());
+    }
+    // the current items first, then the older ones that are still remembered
+    let mut links = items.into_iter().map(|(_, link)| link).collect::<Vec<_>>();
+    seen.retain(|l| !links.contains(l));
+    links.append(&mut seen);
+    links.truncate(SEEN_LIMIT);
+    Ok(Diff {
+        items: new,
+        latest: Some(serde_json::to_string(&links).unwrap_or_default()),
+        ..Default::default()
+    })
+}
+
+/// fetch a page and send what is new since last time, returns the http status
+async fn check(p: &Page) -> Result<u16, MyError> {
+    info!("fetch page {}", p.url);
+    let resp = fetch(&p.url).await?;
+    let status = resp.status().as_u16();
+    let html = resp.text().await?;
+    let diff = diff(p, &html)?;
+    // only removed lines is not worth a notification
+    if !diff.lines.is_empty() {
+        let summary = diff.lines.join("\n");
+        if let Err(e) = insert_entry(
+            &get_conn(),
+            "page",
+            p.id,
+            &p.title,
+            &p.title,
+            &p.url,
+            &summary,
+        ) {
+            report(MyError::db(e, "page", p.id));
+        }
+        let text = format!(
+            "[{}]({}) changed\n{}",
+            p.title,
+            p.url,
+            escape_markdown(&summary)
+        );
+        notify_sub(
+            Kind::Page,
+            p.id,
+            Content::Text {
+                text,
+                preview: false,
+            },
+        )
+        .await;
+    }
+    let mut msg = String::new();
+    for (title, link) in &diff.items {
+        info!("new item [{}]({})", title, link);
+        if let Err(e) = insert_entry(&get_conn(), "page", p.id, &p.title, title, link, "") {
+            report(MyError::db(e, "page", p.id));
+        }
+        msg.push_str(&format!("\n[{}]({})", title, link));
+    }
+    if !msg.is_empty() {
+        notify_sub(
+            Kind::Page,
+            p.id,
+            Content::Text {
+                text: msg.trim_start().to_owned(),
+                preview: false,
+            },
+        )
+        .await;
+    }
+    if let Some(latest) = diff.latest {
+        if let Err(e) = update_page(&get_conn(), p.id, &latest) {
+            report(MyError::db(e, "page", p.id));
+        }
+    }
+    Ok(status)
+}
+
+struct Sub {}
+
+#[async_trait]
+impl Callback for Sub {
+    async fn callback(&self, ctx: &Context) {
+        // `/psub <url> [<item> | <title> | <link>]`, selectors may have spaces
+        let Some(url) = ctx.arg(0) else {
+            ctx.reply("need url, then optionally item | title | link selectors")
+                .await;
+            return;
+        };
+        let selectors = ctx.args[1..].join(" ");
+        let mut selectors = selectors.split('|').map(str::trim);
+        let item_sel = selectors.next().unwrap_or_default();
+        let title_sel = selectors.next().unwrap_or_default();
+        let link_sel = selectors.next().unwrap_or_default();
+        let html = match fetch(url).await {
+            Ok(resp) => resp.text().await.map_err(MyError::Request),
+            Err(e) => Err(e),
+        };
+        let html = match html {
+            Ok(html) => html,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let title = page_text(&html).0.unwrap_or(url.to_owned());
+        let mut p = Page {
+            id: 0,
+            url: url.to_owned(),
+            title,
+            item_sel: item_sel.to_owned(),
+            title_sel: title_sel.to_owned(),
+            link_sel: link_sel.to_owned(),
+            latest: String::new(),
+            health: Default::default(),
+        };
+        // start from what is there now, so only later changes are notified
+        p.latest = match snapshot(&p, &html) {
+            Ok(latest) => latest,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        if !p.item_sel.is_empty() && p.latest == "[]" {
+            ctx.reply("no items matched").await;
+            return;
+        }
+        let selectors = (item_sel, title_sel, link_sel);
+        match insert_page(&get_conn(), &p.url, &p.title, selectors, &p.latest) {
+            Ok(_) => ctx.reply(&format!("watching \"{}\"", p.title)).await,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+            }
+        }
+    }
+}
+
+struct Unsub {}
+
+#[async_trait]
+impl Callback for Unsub {
+    async fn callback(&self, ctx: &Context) {
+        if ctx.step() == Some("confirm") {
+            let Some(id) = ctx.confirmed().and_then(|id| id.parse::<i32>().ok()) else {
+                ctx.reply("cancelled").await;
+                return;
+            };
+            let reply = match delete_page(&get_conn(), id) {
+                Ok(0) => "not found",
+                Ok(_) => "done",
+                Err(e) => {
+                    ctx.reply(&report(e)).await;
+                    return;
+                }
+            };
+            ctx.reply(reply).await;
+            return;
+        }
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id to del").await;
+            return;
+        };
+        match get_page(&get_conn(), id) {
+            Ok(Some(p)) => {
+                let question = format!("unsubscribe #{} \"{}\"?", id, escape_markdown(&p.title));
+                ctx.ask_confirm(&question, &id.to_string()).await;
+            }
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "page",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+            }
+            Err(e) => ctx.reply(&report(e)).await,
+        }
+    }
+}
+
+struct Retry {}
+
+#[async_trait]
+impl Callback for Retry {
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id to retry").await;
+            return;
+        };
+        let conn = get_conn();
+        let p = match get_page(&conn, id) {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "page",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+                return;
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        if let Err(e) = reset_health(&conn, "page", id) {
+            report(MyError::db(e, "page", id));
+        }
+        drop(conn);
+        let res = check(&p).await;
+        record(Kind::Page, p.id, &p.title, res.as_ref().copied()).await;
+        match res {
+            Ok(_) => ctx.reply("done, polling again").await,
+            Err(e) => ctx.reply(&format!("still failing: {}", e.reply())).await,
+        }
+    }
+}
+
+/// watched web pages, polled every `RSS_INTERVAL` seconds
+pub struct PageModule {}
+
+impl Module for PageModule {
+    fn name(&self) -> &'static str {
+        "page"
+    }
+
+    fn migrate(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        db::init_page(conn)
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        dispatcher.register("/page", Role::Reader, Box::new(List {}));
+        dispatcher.register("/psub", Role::Admin, Box::new(Sub {}));
+        dispatcher.register("/punsub", Role::Admin, Box::new(Unsub {}));
+        dispatcher.register("/pretry", Role::Admin, Box::new(Retry {}));
+    }
+
+    fn check_settings(&self) -> Result<(), MyError> {
+        config::<u64>("RSS_INTERVAL").map(|_| ())
+    }
+
+    fn task(&self) -> Option<BoxFuture<'static, ()>> {
+        Some(Box::pin(page_monitor_loop()))
+    }
+}
+
+/// pages are polled on the same schedule as feeds
+pub async fn page_monitor_loop() {
+    let interval: u64 = match config("RSS_INTERVAL") {
+        Ok(interval) => interval,
+        Err(e) => {
+            report(e);
+            return;
+        }
+    };
+    loop {
+        let conn = get_conn();
+        let ps = list_page(&conn).unwrap_or_else(|e| {
+            report(e);
+            vec![]
+        });
+        for p in ps.iter().filter(|p| !p.health.paused) {
+            let res = check(p).await;
+            record(Kind::Page, p.id, &p.title, res.as_ref().copied()).await;
+        }
+        drop(conn);
+        sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::serve_fixtures;
+
+    fn serve() -> String {
+        serve_fixtures("page")
+    }
+
+    async fn get(url: &str) -> String {
+        fetch(url).await.unwrap().text().await.unwrap()
+    }
+
+    fn page(url: &str, item_sel: &str, title_sel: &str) -> Page {
+        Page {
+            id: 1,
+            url: url.to_owned(),
+            title: "test".to_owned(),
+            item_sel: item_sel.to_owned(),
+            title_sel: title_sel.to_owned(),
+            link_sel: String::new(),
+            latest: String::new(),
+            health: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn extract_items_from_fixture() {
+        let url = format!("{}/list.html", serve());
+        let html = get(&url).await;
+        let base = url::Url::parse(&url).unwrap();
+        let items = extract_items(&html, &base, "li.item", "", "").unwrap();
+        let root = url.trim_end_matches("/list.html");
+        assert_eq!(
+            items,
+            vec![
+                (
+                    "Second release is out 2026-10-02".to_owned(),
+                    format!("{}/news/2", root)
+                ),
+                (
+                    "First post read more".to_owned(),
+                    "https://example.org/news/1".to_owned()
+                ),
+            ]
+        );
+        // an item without the title selector is left out
+        let items = extract_items(&html, &base, "li.item", "h3", "").unwrap();
+        assert_eq!(
+            items,
+            vec![(
+                "First post".to_owned(),
+                "https://example.org/news/1".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn bad_selector() {
+        let base = url::Url::parse("http://example.org/").unwrap();
+        let err = extract_items("", &base, "li[", "", "").unwrap_err();
+        assert!(matches!(err, MyError::Selector { selector, .. } if selector == "li["));
+    }
+
+    #[tokio::test]
+    async fn page_text_from_fixture() {
+        let html = get(&format!("{}/text.html", serve())).await;
+        let (title, text) = page_text(&html);
+        assert_eq!(title.as_deref(), Some("Opening hours"));
+        assert_eq!(
+            text,
+            "Opening hours\nMonday to Friday: 9 to 17\nSaturday: closed"
+        );
+    }
+
+    #[tokio::test]
+    async fn diff_new_items() {
+        let root = serve();
+        let mut p = page(&format!("{}/list.html", root), "li.item", "");
+        let html = get(&p.url).await;
+        p.latest = snapshot(&p, &html).unwrap();
+        assert_eq!(diff(&p, &html).unwrap(), Diff::default());
+
+        let html = get(&format!("{}/list_new.html", root)).await;
+        let d = diff(&p, &html).unwrap();
+        let third = format!("{}/news/3", root);
+        assert_eq!(d.items, vec![("Third release".to_owned(), third.clone())]);
+        let latest: Vec<String> = serde_json::from_str(&d.latest.unwrap()).unwrap();
+        assert_eq!(
+            latest,
+            vec![
+                third,
+                format!("{}/news/2", root),
+                "https://example.org/news/1".to_owned()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn diff_changed_text() {
+        let root = serve();
+        let mut p = page(&format!("{}/text.html", root), "", "");
+        let html = get(&p.url).await;
+        p.latest = snapshot(&p, &html).unwrap();
+        assert_eq!(diff(&p, &html).unwrap(), Diff::default());
+
+        let html = get(&format!("{}/text_changed.html", root)).await;
+        let d = diff(&p, &html).unwrap();
+        assert_eq!(d.lines, vec!["Saturday: 10 to 14".to_owned()]);
+        assert_eq!(d.latest.as_deref(), Some(page_text(&html).1.as_str()));
+    }
+
+    #[tokio::test]
+    async fn no_items_matched() {
+        let p = page(&format!("{}/list.html", serve()), "article", "");
+        let html = get(&p.url).await;
+        assert!(matches!(diff(&p, &html), Err(MyError::NotFound { .. })));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::acl::Role;
+use crate::bot::Module;
+use crate::db::{get_conn, get_health, get_pause, reset_health, set_pause, Pause};
+use crate::dispatcher::{Callback, Context, Dispatcher};
+use crate::error::{report, MyError};
+use crate::health::{format_time, Kind};
+#[cfg(feature = "repo")]
+use crate::repo;
+#[cfg(feature = "rss")]
+use crate::rss;
+use crate::utils::{parse_duration, Content};
+#[cfg(feature = "watch")]
+use crate::watch;
+use async_trait::async_trait;
+
+/// how a paused subscription shows in a list, empty when it runs
+pub fn describe(pause: &Pause) -> String {
+    if !pause.held() {
+        return String::new();
+    }
+    match pause.until {
+        Some(until) => format!(" (snoozed until {})", format_time(Some(until))),
+        None => " (paused)".to_owned(),
+    }
+}
+
+/// the pause of the subscription in the first argument, after telling the
+/// chat what is wrong with it
+async fn find(ctx: &Context, kind: Kind) -> Option<(i32, Pause)> {
+    let Some(id) = ctx.arg(0).and_then(|id| id.parse::<i32>().ok()) else {
+        ctx.reply("need id").await;
+        return None;
+    };
+    match get_pause(&get_conn(), kind.table(), id) {
+        Ok(Some(pause)) => Some((id, pause)),
+        Ok(None) => {
+            let e = MyError::NotFound {
+                what: kind.table(),
+                key: id.to_string(),
+            };
+            ctx.reply(&e.reply()).await;
+            None
+        }
+        Err(e) => {
+            ctx.reply(&report(e)).await;
+            None
+        }
+    }
+}
+
+/// stop polling until `/resume`
+struct PauseSub {
+    kind: Kind,
+}
+
+#[async_trait]
+impl Callback for PauseSub {
+    async fn callback(&self, ctx: &Context) {
+        let Some((id, pause)) = find(ctx, self.kind).await else {
+            return;
+        };
+        let now = chrono::Utc::now().timestamp();
+        // pausing a snoozed one keeps what it missed since the snooze
+        let since = pause.since.filter(|_| pause.held()).unwrap_or(now);
+        let reply = match set_pause(&get_conn(), self.kind.table(), id, Some(since), None) {
+            Ok(_) => format!("{} {} paused", self.kind.table(), id),
+            Err(e) => report(e),
+        };
+        ctx.reply(&reply).await;
+    }
+}
+
+/// stop polling for a while, like `3d`, then catch up
+struct SnoozeSub {
+    kind: Kind,
+}
+
+#[async_trait]
+impl Callback for SnoozeSub {
+    async fn callback(&self, ctx: &Context) {
+        let Some(duration) = ctx.arg(1).and_then(parse_duration) else {
+            ctx.reply("need id and how long, like 90m, 12h, 3d or 2w")
+                .await;
+            return;
+        };
+        let Some((id, pause)) = find(ctx, self.kind).await else {
+            return;
+        };
+        let now = chrono::Utc::now().timestamp();
+        let since = pause.since.filter(|_| pause.held()).unwrap_or(now);
+        let until = now + duration;
+        let reply = match set_pause(&get_conn(), self.kind.table(), id, Some(since), Some(until)) {
+            Ok(_) => format!(
+                "{} {} snoozed until {}",
+                self.kind.table(),
+                id,
+                format_time(Some(until))
+            ),
+            Err(e) => report(e),
+        };
+        ctx.reply(&reply).await;
+    }
+}
+
+/// `<id>` asks whether to catch up on what was missed, `<id> catchup` and
+/// `<id> skip` resume; one paused after failures resumes right away
+struct ResumeSub {
+    kind: Kind,
+}
+
+#[async_trait]
+impl Callback for ResumeSub {
+    async fn callback(&self, ctx: &Context) {
+        let Some((id, pause)) = find(ctx, self.kind).await else {
+            return;
+        };
+        let table = self.kind.table();
+        let failed = match get_health(&get_conn(), table, id) {
+            Ok(health) => health.is_some_and(|h| h.paused),
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        if !pause.held() && !failed {
+            ctx.reply(&format!("{} {} is not paused", table, id)).await;
+            return;
+        }
+        match ctx.arg(1) {
+            _ if !pause.held() => {}
+            Some("catchup") => {}
+            Some("skip") => {
+                let skipped: Result<(), MyError> = match self.kind {
+                    #[cfg(feature = "rss")]
+                    Kind::Rss => rss::skip_to_now(id).await,
+                    #[cfg(feature = "repo")]
+                    Kind::Repo => repo::skip_to_now(id).await,
+                    #[cfg(feature = "watch")]
+                    Kind::Watch => watch::skip_to_now(id).await,
+                    #[allow(unreachable_patterns)]
+                    _ => Ok(()),
+                };
+                if let Err(e) = skipped {
+                    ctx.reply(&report(e)).await;
+                    return;
+                }
+            }
+            _ => {
+                let text = format!(
+                    "{} {} is paused since {}, catch up on what was missed?",
+                    table,
+                    id,
+                    format_time(pause.since)
+                );
+                let buttons = vec![
+                    ("catch up".to_owned(), format!("{} {} catchup", ctx.cmd, id)),
+                    ("skip to now".to_owned(), format!("{} {} skip", ctx.cmd, id)),
+                ];
+                ctx.reply_content(Content::Choice { text, buttons }).await;
+                return;
+            }
+        }
+        let conn = get_conn();
+        let res = set_pause(&conn, table, id, None, None).and(reset_health(&conn, table, id));
+        let reply = match res {
+            Ok(_) => format!("{} {} resumed", table, id),
+            Err(e) => report(e),
+        };
+        ctx.reply(&reply).await;
+    }
+}
+
+/// `/pause`, `/snooze` and `/resume` for feeds, the same with `r` for repos
+/// and `w` for watches
+pub struct PauseModule {}
+
+impl Module for PauseModule {
+    fn name(&self) -> &'static str {
+        "pause"
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        let cmds = [
+            (Kind::Rss, "/pause", "/snooze", "/resume"),
+            (Kind::Repo, "/rpause", "/rsnooze", "/rresume"),
+            (Kind::Watch, "/wpause", "/wsnooze", "/wresume"),
+        ];
+        for (kind, pause, snooze, resume) in cmds.into_iter().filter(|c| c.0.enabled()) {
+            dispatcher.register(pause, Role::Admin, Box::new(PauseSub { kind }));
+            dispatcher.register(snooze, Role::Admin, Box::new(SnoozeSub { kind }));
+            dispatcher.register(resume, Role::Admin, Box::new(ResumeSub { kind }));
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use crate::acl::Role;
+use crate::bot::Module;
+use crate::db::{
+    self, delete_reminder, due_reminders, get_chat_tz, get_conn, insert_reminder, list_reminders,
+    set_chat_tz, set_reminder_next,
+};
+use crate::dispatcher::{Callback, Context, Dispatcher, OnEdit};
+use crate::error::report;
+use crate::utils::{escape_markdown, parse_duration, send_to, sleep, timezone, Content, Target};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeDelta, Weekday};
+use chrono_tz::Tz;
+use futures::future::BoxFuture;
+use log::info;
+use rusqlite::Connection;
+
+/// the time of day when a reminder gives none
+const DEFAULT_TIME: &str = "09:00";
+
+const USAGE: &str = "when should be like 2h, 9:00, tomorrow 9:00, friday 18:00, \
+2026-12-31 23:00 or every weekday 10:00";
+
+/// the timezone of a chat, set with `/timezone`, or else `TIMEZONE`
+pub fn chat_timezone(cid: &str) -> Tz {
+    match get_chat_tz(&get_conn(), cid) {
+        Ok(tz) => tz.and_then(|tz| tz.parse().ok()).unwrap_or_else(timezone),
+        Err(e) => {
+            report(e);
+            timezone()
+        }
+    }
+}
+
+/// the days a repeating reminder fires on
+#[derive(Clone, Copy)]
+enum Days {
+    Every,
+    Weekdays,
+    Weekends,
+    On(Weekday),
+}
+
+impl Days {
+    fn parse(word: &str) -> Option<Self> {
+        match word {
+            "day" | "daily" => Some(Days::Every),
+            "weekday" | "weekdays" => Some(Days::Weekdays),
+            "weekend" | "weekends" => Some(Days::Weekends),
+            day => day.parse().ok().map(Days::On),
+        }
+    }
+
+    fn matches(&self, day: Weekday) -> bool {
+        match self {
+            Days::Every => true,
+            Days::Weekdays => day.num_days_from_monday() < 5,
+            Days::Weekends => day.num_days_from_monday() >= 5,
+            Days::On(d) => *d == day,
+        }
+    }
+}
+
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(word, "%H:%M").ok()
+}
+
+fn local_date(tz: Tz, ts: i64) -> NaiveDate {
+    DateTime::from_timestamp(ts, 0)
+        .unwrap_or_default()
+        .with_timezone(&tz)
+        .date_naive()
+}
+
+/// a time of day in `tz`, an hour later if daylight saving skips it
+fn local_ts(tz: Tz, date: NaiveDate, time: NaiveTime) -> i64 {
+    let naive = date.and_time(time);
+    naive
+        .and_local_timezone(tz)
+        .earliest()
+        .or_else(|| {
+            (naive + TimeDelta::hours(1))
+                .and_local_timezone(tz)
+                .earliest()
+        })
+        .map(|t| t.timestamp())
+        .unwrap_or_else(|| naive.and_utc().timestamp())
+}
+
+/// the first `time` on one of `days` after `after`
+fn next_on(days: Days, time: NaiveTime, tz: Tz, after: i64) -> i64 {
+    local_date(tz, after)
+        .iter_days()
+        .take(8)
+        .filter(|d| days.matches(d.weekday()))
+        .map(|d| local_ts(tz, d, time))
+        .find(|&ts| ts > after)
+        .unwrap_or(after + 86400)
+}
+
+/// the next time a rule like "weekday 10:00" fires after `after`
+fn next_by_rule(rule: &str, tz: Tz, after: i64) -> Option<i64> {
+    let (days, time) = rule.split_once(' ')?;
+    Some(next_on(Days::parse(days)?, parse_time(time)?, tz, after))
+}
+
+/// when a reminder fires first, the rule it repeats by, and how many words
+/// of `args` that took
+fn parse_when(args: &[String], tz: Tz, now: i64) -> Option<(i64, Option<String>, usize)> {
+    let word = |i: usize| args.get(i).map(|w| w.to_lowercase());
+    // the time after the day, or the default one
+    let time_at = |i: usize| match args.get(i).and_then(|w| parse_time(w)) {
+        Some(time) => (time, i + 1),
+        None => (parse_time(DEFAULT_TIME).unwrap_or_default(), i),
+    };
+    let first = word(0)?;
+    if first == "every" {
+        let days = word(1)?;
+        let (time, n) = time_at(2);
+        let rule = format!("{} {}", days, time.format("%H:%M"));
+        let next = next_by_rule(&rule, tz, now)?;
+        return Some((next, Some(rule), n));
+    }
+    if first == "in" {
+        return parse_duration(&word(1)?).map(|secs| (now + secs, None, 2));
+    }
+    if let Some(secs) = parse_duration(&first) {
+        return Some((now + secs, None, 1));
+    }
+    if let Some(time) = parse_time(&first) {
+        return Some((next_on(Days::Every, time, tz, now), None, 1));
+    }
+    if let Ok(day) = first.parse::<Weekday>() {
+        let (time, n) = time_at(1);
+        return Some((next_on(Days::On(day), time, tz, now), None, n));
+    }
+    let today = local_date(tz, now);
+    let date = match first.as_str() {
+        "today" => today,
+        "tomorrow" => today.succ_opt()?,
+        date => NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?,
+    };
+    let (time, n) = time_at(1);
+    // a time that passed is a typo rather than a wish to be reminded now
+    Some((local_ts(tz, date, time), None, n)).filter(|(ts, _, _)| *ts > now)
+}
+
+fn format_local(ts: i64, tz: Tz) -> String {
+    DateTime::from_timestamp(ts, 0)
+        .unwrap_or_default()
+        .with_timezone(&tz)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// `<when> <text>`, see `USAGE` for when
+struct Remind {}
+
+#[async_trait]
+impl Callback for Remind {
+    async fn callback(&self, ctx: &Context) {
+        let tz = chat_timezone(&ctx.cid);
+        let now = chrono::Utc::now().timestamp();
+        let Some((next, rule, n)) = parse_when(&ctx.args, tz, now) else {
+            ctx.reply(USAGE).await;
+            return;
+        };
+        let text = ctx.rest(n);
+        if text.is_empty() {
+            ctx.reply("need something to remind of").await;
+            return;
+        }
+        let conn = get_conn();
+        let id = match insert_reminder(&conn, &ctx.cid, ctx.thread, text, next, rule.as_deref()) {
+            Ok(id) => id,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let every = rule.map(|r| format!(", every {}", r)).unwrap_or_default();
+        let reply = format!(
+            "reminder {} at {} {}{}",
+            id,
+            format_local(next, tz),
+            tz,
+            every
+        );
+        ctx.reply(&escape_markdown(&reply)).await;
+    }
+
+    /// an edit would add a second reminder
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Ignore
+    }
+}
+
+struct Reminders {}
+
+#[async_trait]
+impl Callback for Reminders {
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Rerun
+    }
+
+    async fn callback(&self, ctx: &Context) {
+        let rs = match list_reminders(&get_conn(), &ctx.cid) {
+            Ok(rs) => rs,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let tz = chat_timezone(&ctx.cid);
+        let reply = rs
+            .into_iter()
+            .map(|r| {
+                let every = r.rule.map(|r| format!(" every {}", r)).unwrap_or_default();
+                let line = format!("{} {}{}: {}", r.id, format_local(r.next, tz), every, r.text);
+                escape_markdown(&line)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if reply.is_empty() {
+            ctx.reply("no results").await;
+        } else {
+            ctx.reply(&reply).await;
+        }
+    }
+}
+
+struct Unremind {}
+
+#[async_trait]
+impl Callback for Unremind {
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|id| id.parse::<i64>().ok()) else {
+            ctx.reply("need id").await;
+            return;
+        };
+        let reply = match delete_reminder(&get_conn(), &ctx.cid, id) {
+            Ok(n) => {
+                if n > 0 {
+                    "done"
+                } else {
+                    "not found"
+                }
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        ctx.reply(reply).await;
+    }
+
+    /// an edit would delete whichever reminder it names now
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Ignore
+    }
+}
+
+/// `/timezone` shows the timezone of the chat, `/timezone Europe/Berlin` sets it
+struct SetTimezone {}
+
+#[async_trait]
+impl Callback for SetTimezone {
+    async fn callback(&self, ctx: &Context) {
+        let Some(name) = ctx.arg(0) else {
+            let reply = format!("the timezone here is {}", chat_timezone(&ctx.cid));
+            ctx.reply(&escape_markdown(&reply)).await;
+            return;
+        };
+        let Ok(tz) = name.parse::<Tz>() else {
+            ctx.reply("not a timezone, try one like Europe/Berlin")
+                .await;
+            return;
+        };
+        let reply = match set_chat_tz(&get_conn(), &ctx.cid, tz.name()) {
+            Ok(_) => format!("the timezone here is {} now", tz),
+            Err(e) => report(e),
+        };
+        ctx.reply(&escape_markdown(&reply)).await;
+    }
+}
+
+/// send the reminders that are due, also those missed while the bot was down
+async fn remind_loop() {
+    loop {
+        let now = chrono::Utc::now().timestamp();
+        let due = due_reminders(&get_conn(), now).unwrap_or_else(|e| {
+            report(e);
+            vec![]
+        });
+        for r in due {
+            info!("reminder {} in chat {}", r.id, r.cid);
+            let target = Target {
+                thread: r.thread,
+                ..Target::chat(&r.cid)
+            };
+            let content = Content::Text {
+                text: escape_markdown(&format!("reminder: {}", r.text)),
+                preview: false,
+            };
+            send_to(target, content).await;
+            let conn = get_conn();
+            let next = r
+                .rule
+                .as_deref()
+                .and_then(|rule| next_by_rule(rule, chat_timezone(&r.cid), now));
+            let res = match next {
+                Some(next) => set_reminder_next(&conn, r.id, next),
+                None => delete_reminder(&conn, &r.cid, r.id),
+            };
+            if let Err(e) = res {
+                report(e);
+            }
+        }
+        sleep(30).await;
+    }
+}
+
+/// `/remind`, `/reminders`, `/unremind` and the `/timezone` they are in
+pub struct RemindModule {}
+
+impl Module for RemindModule {
+    fn name(&self) -> &'static str {
+        "remind"
+    }
+
+    fn migrate(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        db::init_remind(conn)
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        dispatcher.register("/remind", Role::Reader, Box::new(Remind {}));
+        dispatcher.register("/reminders", Role::Reader, Box::new(Reminders {}));
+        dispatcher.register("/unremind", Role::Reader, Box::new(Unremind {}));
+        dispatcher.register("/timezone", Role::Admin, Box::new(SetTimezone {}));
+    }
+
+    fn task(&self) -> Option<BoxFuture<'static, ()>> {
+        Some(Box::pin(remind_loop()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TZ: Tz = chrono_tz::Europe::Berlin;
+
+    /// a unix timestamp from a utc time like 2026-10-19T08:00:00Z
+    fn at(utc: &str) -> i64 {
+        DateTime::parse_from_rfc3339(utc).unwrap().timestamp()
+    }
+
+    fn when(text: &str, now: &str) -> Option<(i64, Option<String>, usize)> {
+        let args = text
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        parse_when(&args, TZ, at(now))
+    }
+
+    // monday 2026-10-19 10:00 in berlin, still on summer time
+    const NOW: &str = "2026-10-19T08:00:00Z";
+
+    #[test]
+    fn durations() {
+        assert_eq!(when("2h call", NOW), Some((at(NOW) + 7200, None, 1)));
+        assert_eq!(when("in 90m call", NOW), Some((at(NOW) + 5400, None, 2)));
+    }
+
+    #[test]
+    fn times_of_day() {
+        // a time that passed today is tomorrow
+        assert_eq!(
+            when("9:00 x", NOW),
+            Some((at("2026-10-20T07:00:00Z"), None, 1))
+        );
+        assert_eq!(
+            when("18:00 x", NOW),
+            Some((at("2026-10-19T16:00:00Z"), None, 1))
+        );
+    }
+
+    #[test]
+    fn days() {
+        let tomorrow = at("2026-10-20T07:00:00Z");
+        assert_eq!(when("tomorrow 9:00 x", NOW), Some((tomorrow, None, 2)));
+        // without a time it is the default one
+        assert_eq!(when("tomorrow x", NOW), Some((tomorrow, None, 1)));
+        let friday = at("2026-10-23T16:00:00Z");
+        assert_eq!(when("friday 18:00 x", NOW), Some((friday, None, 2)));
+        // winter time by new year
+        let eve = at("2026-12-31T22:00:00Z");
+        assert_eq!(when("2026-12-31 23:00 x", NOW), Some((eve, None, 2)));
+    }
+
+    #[test]
+    fn past_date_is_refused() {
+        assert_eq!(when("2020-01-01 x", NOW), None);
+        assert_eq!(when("today 9:00 x", NOW), None);
+    }
+
+    #[test]
+    fn repeating() {
+        // friday after ten is next monday for weekdays
+        let friday = "2026-10-23T09:00:00Z";
+        let monday = at("2026-10-26T09:00:00Z");
+        let rule = Some("weekday 10:00".to_owned());
+        assert_eq!(
+            when("every weekday 10:00 x", friday),
+            Some((monday, rule, 3))
+        );
+        assert_eq!(
+            next_by_rule("weekday 10:00", TZ, monday),
+            Some(at("2026-10-27T09:00:00Z"))
+        );
+        assert_eq!(
+            next_by_rule("sunday 09:00", TZ, at(NOW)),
+            Some(at("2026-10-25T08:00:00Z"))
+        );
+        assert_eq!(next_by_rule("fortnight 10:00", TZ, at(NOW)), None);
+    }
+
+    #[test]
+    fn daylight_saving() {
+        // 02:30 does not exist on 2026-03-29, the clocks go from 02:00 to 03:00
+        let date = NaiveDate::from_ymd_opt(2026, 3, 29).unwrap();
+        let time = parse_time("02:30").unwrap();
+        assert_eq!(local_ts(TZ, date, time), at("2026-03-29T01:30:00Z"));
+        // 02:30 happens twice on 2026-10-25, the first one counts
+        let date = NaiveDate::from_ymd_opt(2026, 10, 25).unwrap();
+        assert_eq!(local_ts(TZ, date, time), at("2026-10-25T00:30:00Z"));
+        // a daily reminder keeps its local time across the change
+        let rule = "day 09:00";
+        let saturday = at("2026-10-24T07:00:00Z");
+        assert_eq!(
+            next_by_rule(rule, TZ, saturday),
+            Some(at("2026-10-25T08:00:00Z"))
+        );
+    }
+}
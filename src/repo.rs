@@ -1,34 +1,55 @@
-use crate::db::{delete_repo, get_conn, insert_repo, list_repo, update_repo};
-use crate::dispatcher::{Callback, Dispatcher};
-use crate::error::MyError;
-use crate::utils::{send, sleep};
+use crate::acl::Role;
+use crate::bot::Module;
+use crate::db::{
+    self, delete_repo, end_snoozes, find_repo, get_conn, get_repo, insert_entry, insert_repo,
+    list_repo, reset_health, set_repo_template, update_repo, Repo,
+};
+use crate::dispatcher::{Callback, Context, Dispatcher, OnEdit};
+use crate::error::{report, MyError};
+use crate::health::{record, Kind};
+use crate::pause::describe;
+use crate::route::notify_sub;
+use crate::template::Template;
+use crate::utils::{canonical_repo, config, escape_markdown, fetch, sleep, Content};
 use async_trait::async_trait;
+use futures::future::BoxFuture;
 use log::{error, info};
+use rusqlite::Connection;
 
 struct List {}
 
 #[async_trait]
 impl Callback for List {
-    async fn callback(&self, cid: &str, _: &str) {
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Rerun
+    }
+
+    async fn callback(&self, ctx: &Context) {
         let conn = get_conn();
-        let rs = list_repo(&conn).unwrap_or_else(|e| {
-            error!("{}", e);
-            vec![]
-        });
+        let rs = match list_repo(&conn) {
+            Ok(rs) => rs,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
         let reply = rs
             .into_iter()
             .map(|r| {
                 format!(
-                    "{0} [{1}](https://github.com/{1}) {2}",
-                    r.id, r.name, r.latest
+                    "{0} [{1}](https://github.com/{1}) {2}{3}",
+                    r.id,
+                    r.name,
+                    r.latest,
+                    describe(&r.pause)
                 )
             })
             .collect::<Vec<_>>()
             .join("\n");
         if reply.is_empty() {
-            send(cid, "no results").await;
+            ctx.reply("no results").await;
         } else {
-            send(cid, &reply).await;
+            ctx.reply(&reply).await;
         }
     }
 }
@@ -37,24 +58,43 @@ struct Sub {}
 
 #[async_trait]
 impl Callback for Sub {
-    async fn callback(&self, cid: &str, msg: &str) {
-        let Some(name) = msg.split_whitespace().nth(1) else {
-            send(cid, "need repo name").await;
+    async fn callback(&self, ctx: &Context) {
+        let Some(name) = ctx.arg(0) else {
+            ctx.reply("need repo name").await;
             return;
         };
+        // keep the name as typed, unless it is a url
+        let name = &match name.contains("github.com") {
+            true => canonical_repo(name),
+            false => name.to_owned(),
+        };
+        match find_repo(&get_conn(), &canonical_repo(name)) {
+            Ok(Some(id)) => {
+                let reply = format!(
+                    "already subscribed as #{0}, see /rroute {0} for where it posts",
+                    id
+                );
+                ctx.reply(&reply).await;
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                report(e);
+            }
+        }
         let latest = match get_version(name).await {
-            Ok(latest) => latest,
+            Ok((_, latest)) => latest,
             Err(e) => {
-                send(cid, &e.to_string()).await;
+                ctx.reply(&report(e)).await;
                 return;
             }
         };
         match insert_repo(&get_conn(), name, &latest) {
             Ok(_) => {
-                send(cid, &format!("OK, latest is {}", latest)).await;
+                ctx.reply(&format!("OK, latest is {}", latest)).await;
             }
             Err(e) => {
-                send(cid, &e.to_string()).await;
+                ctx.reply(&report(e)).await;
             }
         }
     }
@@ -64,75 +104,272 @@ struct Unsub {}
 
 #[async_trait]
 impl Callback for Unsub {
-    async fn callback(&self, cid: &str, msg: &str) {
-        let Some(id_to_del) = msg
-            .split_whitespace()
-            .nth(1)
-            .and_then(|t| t.parse::<i32>().ok())
-        else {
-            send(cid, "need id to del").await;
+    async fn callback(&self, ctx: &Context) {
+        if ctx.step() == Some("confirm") {
+            let Some(id) = ctx.confirmed().and_then(|id| id.parse::<i32>().ok()) else {
+                ctx.reply("cancelled").await;
+                return;
+            };
+            let reply = match delete_repo(&get_conn(), id) {
+                Ok(0) => "not found",
+                Ok(_) => "done",
+                Err(e) => {
+                    ctx.reply(&report(e)).await;
+                    return;
+                }
+            };
+            ctx.reply(reply).await;
+            return;
+        }
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id to del").await;
             return;
         };
-        let conn = get_conn();
-        let reply = match delete_repo(&conn, id_to_del) {
-            Ok(n) => {
-                if n > 0 {
-                    "done"
-                } else {
-                    "not found"
-                }
+        match get_repo(&get_conn(), id) {
+            Ok(Some(r)) => {
+                let question = format!("unsubscribe #{} \"{}\"?", id, escape_markdown(&r.name));
+                ctx.ask_confirm(&question, &id.to_string()).await;
             }
-            Err(e) => {
-                error!("{}", e);
-                "error"
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "repo",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
             }
-        };
-        send(cid, reply).await;
+            Err(e) => ctx.reply(&report(e)).await,
+        }
     }
 }
 
-async fn get_version(name: &str) -> Result<String, MyError> {
+/// the latest release of a repo, with the http status of the releases page
+async fn get_version(name: &str) -> Result<(u16, String), MyError> {
     let url = url::Url::parse(&format!("https://github.com/{}/releases", name))?;
-    let text = reqwest::get(url.clone()).await?.text().await?;
-    text.split_whitespace()
+    let resp = fetch(url.as_str()).await?;
+    let status = resp.status().as_u16();
+    let text = resp.text().await?;
+    let latest = text
+        .split_whitespace()
         .filter_map(|l| {
             l.split_once("releases/tag/")
                 .map(|x| x.1.split_once("\"").unwrap().0.to_owned())
         })
         .next()
-        .ok_or(MyError::Custom("no release found".to_owned()))
+        .ok_or(MyError::NotFound {
+            what: "release",
+            key: name.to_owned(),
+        })?;
+    Ok((status, latest))
+}
+
+/// the placeholders a repo's template may use
+const REPO_FIELDS: [&str; 5] = ["repo", "home", "link", "tag", "old_tag"];
+
+const REPO_TEMPLATE: &str = "[{repo}]({home}) {tag}";
+
+/// the message about a new release `tag`, with the template of the repo
+fn format_release(r: &Repo, tag: &str, old_tag: &str) -> String {
+    let template = r
+        .template
+        .as_deref()
+        .and_then(|t| {
+            Template::parse(t, &REPO_FIELDS)
+                .map_err(|e| error!("[{}] template of repo {}: {}", e.label(), r.id, e))
+                .ok()
+        })
+        .unwrap_or_else(|| Template::parse(REPO_TEMPLATE, &REPO_FIELDS).expect("default template"));
+    template.render(|name, _| match name {
+        "repo" => r.name.clone(),
+        "home" => format!("https://github.com/{}", r.name),
+        "link" => format!("https://github.com/{}/releases/tag/{}", r.name, tag),
+        "tag" => tag.to_owned(),
+        "old_tag" => old_tag.to_owned(),
+        _ => String::new(),
+    })
+}
+
+/// `<id>` shows the template of a repo, `<id> off` goes back to the
+/// default, anything else after the id is the new template
+struct SetTemplate {}
+
+#[async_trait]
+impl Callback for SetTemplate {
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id, and a template or off").await;
+            return;
+        };
+        let conn = get_conn();
+        let r = match get_repo(&conn, id) {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "repo",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+                return;
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let text = match ctx.rest(1) {
+            "" => {
+                let fields = REPO_FIELDS.map(|f| format!("{{{}}}", f)).join(" ");
+                let template = r.template.as_deref().unwrap_or(REPO_TEMPLATE);
+                let reply = format!("{}\n\nplaceholders: {}", template, fields);
+                ctx.reply(&escape_markdown(&reply)).await;
+                return;
+            }
+            "off" => None,
+            text => match Template::parse(text, &REPO_FIELDS) {
+                Ok(_) => Some(text),
+                Err(e) => {
+                    ctx.reply(&e.reply()).await;
+                    return;
+                }
+            },
+        };
+        if let Err(e) = set_repo_template(&conn, id, text) {
+            ctx.reply(&report(e)).await;
+            return;
+        }
+        let r = Repo {
+            template: text.map(str::to_owned),
+            ..r
+        };
+        ctx.reply("done, a release will look like this:").await;
+        ctx.reply(&format_release(&r, &r.latest, "v0.0.0")).await;
+    }
+}
+
+/// take the latest release as seen, without telling about it
+pub async fn skip_to_now(id: i32) -> Result<(), MyError> {
+    let r = get_repo(&get_conn(), id)?.ok_or(MyError::NotFound {
+        what: "repo",
+        key: id.to_string(),
+    })?;
+    let (_, latest) = get_version(&r.name).await?;
+    update_repo(&get_conn(), r.id, &latest)?;
+    Ok(())
+}
+
+struct Retry {}
+
+#[async_trait]
+impl Callback for Retry {
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id to retry").await;
+            return;
+        };
+        let conn = get_conn();
+        let r = match get_repo(&conn, id) {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "repo",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+                return;
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        if let Err(e) = reset_health(&conn, "repo", id) {
+            report(MyError::db(e, "repo", id));
+        }
+        drop(conn);
+        let res = check(&r).await;
+        record(Kind::Repo, r.id, &r.name, res.as_ref().copied()).await;
+        match res {
+            Ok(_) => ctx.reply("done, polling again").await,
+            Err(e) => ctx.reply(&format!("still failing: {}", e.reply())).await,
+        }
+    }
+}
+
+/// github release subscriptions, polled every `REPO_INTERVAL` seconds
+pub struct RepoModule {}
+
+impl Module for RepoModule {
+    fn name(&self) -> &'static str {
+        "repo"
+    }
+
+    fn migrate(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        db::init_repo(conn)
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        dispatcher.register("/repo", Role::Reader, Box::new(List {}));
+        dispatcher.register("/rsub", Role::Admin, Box::new(Sub {}));
+        dispatcher.register("/runsub", Role::Admin, Box::new(Unsub {}));
+        dispatcher.register("/rretry", Role::Admin, Box::new(Retry {}));
+        dispatcher.register("/rtemplate", Role::Admin, Box::new(SetTemplate {}));
+    }
+
+    fn check_settings(&self) -> Result<(), MyError> {
+        config::<u64>("REPO_INTERVAL").map(|_| ())
+    }
+
+    fn task(&self) -> Option<BoxFuture<'static, ()>> {
+        Some(Box::pin(repo_monitor_loop()))
+    }
 }
 
-pub fn register(dispatcher: &mut Dispatcher) {
-    dispatcher.register("/repo", Box::new(List {}));
-    dispatcher.register("/rsub", Box::new(Sub {}));
-    dispatcher.register("/runsub", Box::new(Unsub {}));
+/// look up the latest release and tell if it changed
+async fn check(r: &Repo) -> Result<u16, MyError> {
+    info!("fetch repo {}", r.name);
+    let (status, latest) = get_version(&r.name).await?;
+    if latest != r.latest {
+        let link = format!("https://github.com/{}/releases/tag/{}", r.name, latest);
+        let title = format!("{} {}", r.name, latest);
+        if let Err(e) = insert_entry(&get_conn(), "repo", r.id, &r.name, &title, &link, "") {
+            report(MyError::db(e, "repo", r.id));
+        }
+        let text = format_release(r, &latest, &r.latest);
+        notify_sub(
+            Kind::Repo,
+            r.id,
+            Content::Text {
+                text,
+                preview: false,
+            },
+        )
+        .await;
+        if let Err(e) = update_repo(&get_conn(), r.id, &latest) {
+            report(MyError::db(e, "repo", r.id));
+        }
+    }
+    Ok(status)
 }
 
 pub async fn repo_monitor_loop() {
-    let cid = std::env::var("MASTER_ID").unwrap();
-    let interval = std::env::var("REPO_INTERVAL").unwrap().parse().unwrap();
+    let interval: u64 = match config("REPO_INTERVAL") {
+        Ok(interval) => interval,
+        Err(e) => {
+            report(e);
+            return;
+        }
+    };
     loop {
         let conn = get_conn();
+        if let Err(e) = end_snoozes(&conn, "repo") {
+            report(e);
+        }
         let rs = list_repo(&conn).unwrap_or_else(|e| {
-            error!("{}", e);
+            report(e);
             vec![]
         });
-        for r in rs {
-            info!("fetch repo {}", r.name);
-            let latest = match get_version(&r.name).await {
-                Err(e) => {
-                    error!("{}", e);
-                    continue;
-                }
-                Ok(resp) => resp,
-            };
-            if latest != r.latest {
-                send(&cid, &format!("[{0}]({0}) {1}", r.name, r.latest)).await;
-                if let Err(e) = update_repo(&conn, r.id, &latest) {
-                    error!("{}", e);
-                }
-            }
+        for r in rs.iter().filter(|r| !r.health.paused && !r.pause.held()) {
+            let res = check(r).await;
+            record(Kind::Repo, r.id, &r.name, res.as_ref().copied()).await;
         }
         drop(conn);
         sleep(interval).await;
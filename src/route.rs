@@ -0,0 +1,162 @@
+use crate::acl::{notify, notify_roles, Role};
+use crate::bot::{can_post, Module};
+use crate::db::{delete_route, get_conn, get_route, set_route, Route};
+use crate::dispatcher::{Callback, Context, Dispatcher};
+use crate::error::{report, MyError};
+use crate::health::Kind;
+use crate::utils::{send_to, Content, Target};
+use async_trait::async_trait;
+
+/// where a subscription's route sends to, `None` for every reader
+fn route_target(kind: Kind, id: i32) -> Option<Target> {
+    match get_route(&get_conn(), kind.table(), id) {
+        Ok(route) => route.map(|route| Target {
+            thread: route.thread,
+            ..Target::chat(&route.chat)
+        }),
+        Err(e) => {
+            report(MyError::db(e, kind.table(), id));
+            None
+        }
+    }
+}
+
+/// send what a subscription found to its route, or to every reader without one
+pub async fn notify_sub(kind: Kind, id: i32, content: Content) {
+    match route_target(kind, id) {
+        Some(target) => send_to(target, content).await,
+        None => notify(content).await,
+    }
+}
+
+/// tell about trouble with a subscription where its posts go; the admins
+/// get the text with the `buttons`, which readers may not use
+pub async fn alert_sub(kind: Kind, id: i32, text: String, buttons: Vec<(String, String)>) {
+    let plain = Content::Text {
+        text: text.clone(),
+        preview: false,
+    };
+    match route_target(kind, id) {
+        Some(target) => send_to(target, plain).await,
+        None => notify_roles(plain, Role::Reader..=Role::Reader).await,
+    }
+    notify_roles(Content::Choice { text, buttons }, Role::Admin..=Role::Owner).await;
+}
+
+/// where a subscription's notifications end up, as a key to tell apart
+/// those that go to the same place; empty for every reader
+pub fn destination(kind: Kind, id: i32) -> String {
+    match get_route(&get_conn(), kind.table(), id) {
+        Ok(Some(route)) => match route.thread {
+            Some(thread) => format!("{}/{}", route.chat, thread),
+            None => route.chat,
+        },
+        Ok(None) => String::new(),
+        Err(e) => {
+            report(MyError::db(e, kind.table(), id));
+            String::new()
+        }
+    }
+}
+
+fn describe(route: &Route) -> String {
+    match route.thread {
+        Some(thread) => format!("chat {} topic {}", route.chat, thread),
+        None => format!("chat {}", route.chat),
+    }
+}
+
+/// `<id>` shows the route, `<id> off` drops it, `<id> here` routes to the
+/// chat and topic of the command, `<id> <chat> [topic]` to a channel or group
+struct SetRoute {
+    kind: Kind,
+}
+
+#[async_trait]
+impl Callback for SetRoute {
+    async fn callback(&self, ctx: &Context) {
+        let table = self.kind.table();
+        let Some(id) = ctx.arg(0).and_then(|id| id.parse::<i32>().ok()) else {
+            ctx.reply("need id, and a chat, here or off").await;
+            return;
+        };
+        match self.kind.exists(id) {
+            Ok(true) => {}
+            Ok(false) => {
+                let e = MyError::NotFound {
+                    what: table,
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+                return;
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        }
+        let (chat, thread) = match (ctx.arg(1), ctx.arg(2)) {
+            (None, _) => {
+                let reply = match get_route(&get_conn(), table, id) {
+                    Ok(Some(route)) => format!("{} {} goes to {}", table, id, describe(&route)),
+                    Ok(None) => format!("{} {} goes to every reader", table, id),
+                    Err(e) => report(e),
+                };
+                ctx.reply(&reply).await;
+                return;
+            }
+            (Some("off"), _) => {
+                let reply = match delete_route(&get_conn(), table, id) {
+                    Ok(_) => format!("{} {} goes to every reader again", table, id),
+                    Err(e) => report(e),
+                };
+                ctx.reply(&reply).await;
+                return;
+            }
+            (Some("here"), _) => (ctx.cid.as_str(), ctx.thread),
+            (Some(chat), None) => (chat, None),
+            (Some(chat), Some(thread)) => match thread.parse() {
+                Ok(thread) => (chat, Some(thread)),
+                Err(_) => {
+                    ctx.reply("topic should be a numeric id").await;
+                    return;
+                }
+            },
+        };
+        // a username like @team_releases becomes the id, which never changes
+        let chat = match can_post(chat).await {
+            Ok(chat) => chat,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let route = Route { chat, thread };
+        let reply = match set_route(&get_conn(), table, id, &route) {
+            Ok(_) => format!("{} {} goes to {} now", table, id, describe(&route)),
+            Err(e) => report(e),
+        };
+        ctx.reply(&reply).await;
+    }
+}
+
+/// where notifications go: `/route`, `/rroute`, `/proute` and `/wroute`
+pub struct RouteModule {}
+
+impl Module for RouteModule {
+    fn name(&self) -> &'static str {
+        "route"
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        let cmds = [
+            ("/route", Kind::Rss),
+            ("/rroute", Kind::Repo),
+            ("/proute", Kind::Page),
+            ("/wroute", Kind::Watch),
+        ];
+        for (cmd, kind) in cmds.into_iter().filter(|(_, kind)| kind.enabled()) {
+            dispatcher.register(cmd, Role::Admin, Box::new(SetRoute { kind }));
+        }
+    }
+}
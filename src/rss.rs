@@ -1,131 +1,702 @@
-use crate::db::{delete_rss, get_conn, insert_rss, list_rss, update_rss};
-use crate::dispatcher::{Callback, Dispatcher};
-use crate::error::MyError;
-use crate::utils::{send, sleep};
+use crate::acl::{notify, Role};
+use crate::bot::Module;
+use crate::db::{
+    self, change_rss, delete_rss, end_snoozes, find_rss, get_conn, get_rss, get_rss_item,
+    insert_entry, insert_rss, list_rss, list_rss_history, mark_seen, prune_rss_items, prune_seen,
+    put_rss_item, reset_health, seen_since, set_rss_keywords, set_rss_preview, set_rss_summary,
+    set_rss_template, set_rss_updates, update_rss, Rss, RssItem,
+};
+use crate::dispatcher::{Callback, Context, Dispatcher, OnEdit};
+use crate::error::{report, MyError};
+use crate::health::{record, Kind};
+use crate::pause::describe;
+use crate::route::{destination, notify_sub};
+use crate::template::Template;
+use crate::utils::{
+    canonical_url, config, escape_markdown, fetch, fetch_following, resolve_link, setting, sleep,
+    strip_html, timezone, truncate, Content,
+};
 use async_trait::async_trait;
+use feed_rs::model::{Entry, Feed};
+use futures::future::BoxFuture;
 use log::{error, info};
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
 
 struct List {}
 
 #[async_trait]
 impl Callback for List {
-    async fn callback(&self, cid: &str, _: &str) {
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Rerun
+    }
+
+    async fn callback(&self, ctx: &Context) {
         let conn = get_conn();
-        let rs = list_rss(&conn).unwrap_or_else(|e| {
-            error!("{}", e);
-            vec![]
-        });
+        let rs = match list_rss(&conn) {
+            Ok(rs) => rs,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
         let reply = rs
             .into_iter()
-            .map(|r| format!("{} [{}]({})", r.id, r.title, r.home))
+            .map(|r| format!("{} [{}]({}){}", r.id, r.title, r.home, describe(&r.pause)))
             .collect::<Vec<_>>()
             .join("\n");
         if reply.is_empty() {
-            send(cid, "no results").await;
+            ctx.reply("no results").await;
         } else {
-            send(cid, &reply).await;
+            ctx.reply(&reply).await;
         }
     }
 }
 
-fn parse_feed(feed: feed_rs::model::Feed, url_str: &str) -> (String, String, String, String) {
+/// the web page of a feed, as opposed to the link to the feed itself
+fn feed_home(feed: &Feed) -> Option<String> {
+    feed.links
+        .iter()
+        .find(|l| l.rel.as_deref().unwrap_or("alternate") == "alternate")
+        .map(|l| l.href.clone())
+}
+
+fn feed_title(feed: &Feed) -> String {
+    feed.title
+        .as_ref()
+        .map(|title| title.content.clone())
+        .unwrap_or_else(|| "no title".to_owned())
+}
+
+/// parse a feed, leaving the id of an entry that has none empty instead of
+/// making one up from its title, see `entry_key`
+fn parse(url: &str, bytes: &[u8]) -> Result<Feed, MyError> {
+    feed_rs::parser::Builder::new()
+        .id_generator(|_, _, _| String::new())
+        .build()
+        .parse(bytes)
+        .map_err(|e| MyError::Feed {
+            url: url.to_owned(),
+            source: Box::new(e),
+        })
+}
+
+/// the title and link of an entry, empty when it has none
+fn title_link(entry: &Entry) -> (String, String) {
     (
-        feed.links
-            .first()
-            .map(|l| l.href.clone())
-            .unwrap_or(url_str.to_owned()),
-        feed.title
-            .map(|title| title.content)
-            .unwrap_or_else(|| "no title".to_owned()),
-        feed.entries
-            .first()
-            .and_then(|e| e.title.as_ref().map(|t| t.content.clone()))
+        entry
+            .title
+            .as_ref()
+            .map(|t| t.content.clone())
             .unwrap_or_default(),
-        feed.entries
+        entry
+            .links
             .first()
-            .and_then(|e| e.links.first().map(|l| l.href.clone()))
+            .map(|l| l.href.clone())
             .unwrap_or_default(),
     )
 }
 
-async fn process_sub_url(url_str: &str) -> Result<(String, String, String, String), MyError> {
-    let url = url::Url::parse(url_str)?;
-    let bytes = reqwest::get(url.clone()).await?.bytes().await?;
-    match feed_rs::parser::parse(&bytes[..]) {
-        // the url is just feed url
-        Ok(feed) => Ok(parse_feed(feed, url_str)),
-        Err(_) => {
-            // find feed url in page
-            let text = String::from_utf8_lossy(&bytes);
-            match feedfinder::detect_feeds(&url, &text)
-                .ok()
-                .and_then(|feeds| feeds.first().map(|f| f.url().clone()))
-            {
-                None => Err(MyError::Custom("no feed found".to_owned())),
-                Some(feed_url) => {
-                    // how to do recursive async fn?
-                    let url = url::Url::parse(feed_url.as_ref())?;
-                    let bytes = reqwest::get(url.clone()).await?.bytes().await?;
-                    match feed_rs::parser::parse(&bytes[..]) {
-                        Ok(feed) => Ok(parse_feed(feed, url_str)),
-                        Err(_) => Err(MyError::Custom("no feed found".to_owned())),
-                    }
-                }
-            }
+/// what tells an entry apart across polls, even once its title is fixed:
+/// its id, else its link, else its title
+fn entry_key<'a>(title: &'a str, link: &'a str, entry: &'a Entry) -> &'a str {
+    [entry.id.as_str(), link, title]
+        .into_iter()
+        .find(|key| !key.is_empty())
+        .unwrap_or_default()
+}
+
+/// feed url, home, title, and the title, link and key of the latest entry
+fn parse_feed(
+    feed: Feed,
+    feed_url: &str,
+    home: &str,
+) -> (String, String, String, String, String, String) {
+    let (latest_title, latest_link) = feed.entries.first().map(title_link).unwrap_or_default();
+    let latest_key = feed
+        .entries
+        .first()
+        .map(|e| entry_key(&latest_title, &latest_link, e).to_owned())
+        .unwrap_or_default();
+    (
+        feed_url.to_owned(),
+        feed_home(&feed).unwrap_or(home.to_owned()),
+        feed_title(&feed),
+        latest_title,
+        latest_link,
+        latest_key,
+    )
+}
+
+/// where sites usually put their feed, tried when the page advertises none
+const COMMON_PATHS: [&str; 5] = ["/feed", "/rss.xml", "/atom.xml", "/index.xml", "/feed.xml"];
+
+/// the title of the feed at the url, if it is a feed
+async fn probe(url: &url::Url) -> Option<String> {
+    let bytes = fetch(url.as_str()).await.ok()?.bytes().await.ok()?;
+    let feed = parse(url.as_str(), &bytes).ok()?;
+    Some(feed_title(&feed))
+}
+
+/// the feeds for a url as (url, title): the url itself if it is a feed, else
+/// the feeds advertised by the page, else the ones at the usual paths
+async fn discover(url_str: &str) -> Result<Vec<(String, String)>, MyError> {
+    let resp = fetch(url::Url::parse(url_str)?.as_str()).await?;
+    // relative links are relative to where the redirects end up
+    let base = resp.url().clone();
+    let bytes = resp.bytes().await?;
+    if let Ok(feed) = parse(url_str, &bytes) {
+        return Ok(vec![(url_str.to_owned(), feed_title(&feed))]);
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    let mut urls = feedfinder::detect_feeds(&base, &text)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| f.url().clone())
+        .collect::<Vec<_>>();
+    if urls.is_empty() {
+        urls = COMMON_PATHS
+            .iter()
+            .filter_map(|path| base.join(path).ok())
+            .collect();
+    }
+    let mut feeds: Vec<(String, String)> = vec![];
+    for url in urls.into_iter().take(10) {
+        if feeds.iter().any(|(u, _)| u == url.as_str()) {
+            continue;
+        }
+        if let Some(title) = probe(&url).await {
+            feeds.push((url.to_string(), title));
         }
     }
+    Ok(feeds)
 }
 
+/// the id of the feed already subscribed to at the url, as feed or home page
+fn subscribed(url: &str) -> Option<i32> {
+    let canon = canonical_url(url);
+    let conn = get_conn();
+    let found = find_rss(&conn, &canon).unwrap_or_else(|e| {
+        report(e);
+        None
+    });
+    found.or_else(|| {
+        list_rss(&conn)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|r| canonical_url(&r.home) == canon)
+            .map(|r| r.id)
+    })
+}
+
+/// there is one subscription per feed for every chat, where it posts is
+/// up to its route
+async fn reply_subscribed(ctx: &Context, id: i32) {
+    let reply = format!(
+        "already subscribed as #{0}, see /route {0} for where it posts",
+        id
+    );
+    ctx.reply(&reply).await;
+}
+
+/// subscribe to a feed, the id of the new subscription
+async fn subscribe(ctx: &Context, feed_url: &str, page: &str) -> Option<i64> {
+    if let Some(id) = subscribed(feed_url) {
+        reply_subscribed(ctx, id).await;
+        return None;
+    }
+    let feed = match fetch(feed_url).await {
+        Ok(resp) => resp.bytes().await.map_err(MyError::Request),
+        Err(err) => Err(err),
+    }
+    .and_then(|bytes| parse(feed_url, &bytes));
+    let feed = match feed {
+        Ok(feed) => feed,
+        Err(err) => {
+            ctx.reply(&report(err)).await;
+            return None;
+        }
+    };
+    let (feed_str, home_str, title_str, latest_title_str, latest_link_str, latest_key_str) =
+        parse_feed(feed, feed_url, page);
+    let conn = get_conn();
+    if let Err(e) = insert_rss(
+        &conn,
+        &home_str,
+        &title_str,
+        &feed_str,
+        &latest_title_str,
+        &latest_link_str,
+        &latest_key_str,
+    ) {
+        ctx.reply(&report(e)).await;
+        return None;
+    }
+    let id = conn.last_insert_rowid();
+    drop(conn);
+    ctx.reply(&format!("subcribed \"{}\"", title_str)).await;
+    Some(id)
+}
+
+/// `/sub <url>`, or `/sub` alone to be asked for the url; when a site has
+/// several feeds the user picks one, then which posts to send and how they
+/// should look
 struct Sub {}
 
 #[async_trait]
 impl Callback for Sub {
-    async fn callback(&self, cid: &str, msg: &str) {
-        let Some(url_str) = msg.split_whitespace().nth(1) else {
-            send(cid, "need url").await;
-            return;
-        };
-        let (feed_str, title_str, latest_title_str, latest_link_str) =
-            match process_sub_url(url_str).await {
-                Ok(res) => res,
-                Err(err) => {
-                    error!("{}", err);
-                    send(cid, "no feed found").await;
+    async fn callback(&self, ctx: &Context) {
+        let id = match ctx.step() {
+            Some("pick") => {
+                let (page, feeds): (String, Vec<String>) = ctx
+                    .dialogue
+                    .as_ref()
+                    .and_then(|d| serde_json::from_str(&d.data).ok())
+                    .unwrap_or_default();
+                let n = ctx.arg(0).and_then(|n| n.parse::<usize>().ok());
+                let Some(feed) = n.and_then(|n| feeds.get(n.wrapping_sub(1))) else {
+                    ctx.reply("no such choice").await;
                     return;
+                };
+                subscribe(ctx, feed, &page).await
+            }
+            // a new `/sub` leaves the question behind
+            Some("filter") if !ctx.text.starts_with('/') => {
+                let id = ctx
+                    .dialogue
+                    .as_ref()
+                    .and_then(|d| d.data.parse::<i32>().ok());
+                let Some(id) = id else {
+                    ctx.reply("no such choice").await;
+                    return;
+                };
+                if set_filter(ctx, id, &ctx.args.join(" ")).await {
+                    offer_formats(ctx, id).await;
                 }
+                return;
+            }
+            _ => {
+                let Some(url_str) = ctx.arg(0) else {
+                    let question = Content::Text {
+                        text: "send the url of a site or a feed, or /cancel".to_owned(),
+                        preview: false,
+                    };
+                    ctx.ask(question, "url", "").await;
+                    return;
+                };
+                find_feed(ctx, url_str).await
+            }
+        };
+        let Some(id) = id else {
+            return;
+        };
+        let question = Content::Choice {
+            text: "which posts should be sent? send words a post must have, and -words it \
+                   must not, or /cancel to send all"
+                .to_owned(),
+            buttons: vec![("all of them".to_owned(), "all".to_owned())],
+        };
+        ctx.ask(question, "filter", &id.to_string()).await;
+    }
+}
+
+/// buttons for the commands that set how the posts of a feed look
+async fn offer_formats(ctx: &Context, id: i32) {
+    let buttons = [
+        ("titles only", format!("/summary {} off", id)),
+        ("with summaries", format!("/summary {} 300", id)),
+        ("with previews", format!("/preview {} on", id)),
+        ("and updates", format!("/updates {} on", id)),
+    ];
+    let text = "how should new posts look?".to_owned();
+    let buttons = buttons
+        .into_iter()
+        .map(|(label, cmd)| (label.to_owned(), cmd))
+        .collect();
+    ctx.reply_content(Content::Choice { text, buttons }).await;
+}
+
+/// set the keywords of a feed, `all` for none, see `wanted`; whether it
+/// was set, the reply says why not
+async fn set_filter(ctx: &Context, id: i32, keywords: &str) -> bool {
+    let keywords = match keywords.trim() {
+        "" | "all" | "off" => None,
+        keywords => Some(keywords),
+    };
+    match set_rss_keywords(&get_conn(), id, keywords) {
+        Ok(0) => {
+            let e = MyError::NotFound {
+                what: "feed",
+                key: id.to_string(),
             };
-        let conn = get_conn();
-        if let Err(e) = insert_rss(
-            &conn,
-            url_str,
-            &title_str,
-            &feed_str,
-            &latest_title_str,
-            &latest_link_str,
-        ) {
-            error!("{}", e);
-            send(cid, "error in db").await;
-        } else {
-            send(cid, &format!("subcribed \"{}\"", title_str)).await;
+            ctx.reply(&e.reply()).await;
+            false
+        }
+        Ok(_) => {
+            let reply = match keywords {
+                Some(keywords) => format!("done, sending posts with {}", keywords),
+                None => "done, sending all posts".to_owned(),
+            };
+            ctx.reply(&escape_markdown(&reply)).await;
+            true
+        }
+        Err(e) => {
+            ctx.reply(&report(e)).await;
+            false
+        }
+    }
+}
+
+/// whether a post passes the keywords of its feed: it has one of the plain
+/// ones if there are any, and none of those with a `-`, ignoring case
+fn wanted(r: &Rss, title: &str, summary: &str) -> bool {
+    let Some(keywords) = &r.keywords else {
+        return true;
+    };
+    let text = format!("{} {}", title, summary).to_lowercase();
+    let (exclude, include): (Vec<_>, Vec<_>) = keywords
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .partition(|k| k.starts_with('-'));
+    (include.is_empty() || include.iter().any(|k| text.contains(k.as_str())))
+        && !exclude
+            .iter()
+            .any(|k| k.len() > 1 && text.contains(&k[1..]))
+}
+
+/// subscribe to the feed of a site, or ask which one if it has several
+async fn find_feed(ctx: &Context, url_str: &str) -> Option<i64> {
+    if let Some(id) = subscribed(url_str) {
+        reply_subscribed(ctx, id).await;
+        return None;
+    }
+    let feeds = match discover(url_str).await {
+        Ok(feeds) => feeds,
+        Err(err) => {
+            ctx.reply(&report(err)).await;
+            return None;
+        }
+    };
+    match &feeds[..] {
+        [] => {
+            let e = MyError::NotFound {
+                what: "feed",
+                key: url_str.to_owned(),
+            };
+            ctx.reply(&e.reply()).await;
+            None
+        }
+        [(feed, _)] => subscribe(ctx, feed, url_str).await,
+        _ => {
+            let text = feeds
+                .iter()
+                .enumerate()
+                .map(|(i, (url, title))| format!("{}. {} {}", i + 1, escape_markdown(title), url))
+                .collect::<Vec<_>>()
+                .join("\n");
+            // the number is an answer to the question
+            let buttons = feeds
+                .iter()
+                .enumerate()
+                .map(|(i, (_, title))| {
+                    (
+                        format!("{}. {}", i + 1, truncate(title, 40)),
+                        (i + 1).to_string(),
+                    )
+                })
+                .collect();
+            let urls = feeds.into_iter().map(|(url, _)| url).collect::<Vec<_>>();
+            let data = serde_json::to_string(&(url_str, urls)).unwrap_or_default();
+            let text = format!("found several feeds, pick one:\n{}", text);
+            ctx.ask(Content::Choice { text, buttons }, "pick", &data)
+                .await;
+            None
         }
     }
 }
 
+/// `/unsub <id>`, or `/unsub` alone to be asked which, after a confirmation
 struct Unsub {}
 
 #[async_trait]
 impl Callback for Unsub {
-    async fn callback(&self, cid: &str, msg: &str) {
-        let Some(id_to_del) = msg
-            .split_whitespace()
-            .nth(1)
-            .and_then(|t| t.parse::<i32>().ok())
-        else {
-            send(cid, "need id to del").await;
+    async fn callback(&self, ctx: &Context) {
+        if ctx.step() == Some("confirm") {
+            let Some(id) = ctx.confirmed().and_then(|id| id.parse::<i32>().ok()) else {
+                ctx.reply("cancelled").await;
+                return;
+            };
+            let reply = match delete_rss(&get_conn(), id) {
+                Ok(0) => "not found",
+                Ok(_) => "done",
+                Err(e) => {
+                    ctx.reply(&report(e)).await;
+                    return;
+                }
+            };
+            ctx.reply(reply).await;
+            return;
+        }
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            let rs = match list_rss(&get_conn()) {
+                Ok(rs) => rs,
+                Err(e) => {
+                    ctx.reply(&report(e)).await;
+                    return;
+                }
+            };
+            if rs.is_empty() {
+                ctx.reply("no results").await;
+                return;
+            }
+            let list = rs
+                .iter()
+                .map(|r| format!("{} {}", r.id, escape_markdown(&r.title)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let question = Content::Text {
+                text: format!("send the id to unsubscribe, or /cancel\n{}", list),
+                preview: false,
+            };
+            ctx.ask(question, "which", "").await;
             return;
         };
-        let conn = get_conn();
-        let reply = match delete_rss(&conn, id_to_del) {
+        match get_rss(&get_conn(), id) {
+            Ok(Some(r)) => {
+                let question = format!("unsubscribe #{} \"{}\"?", id, escape_markdown(&r.title));
+                ctx.ask_confirm(&question, &id.to_string()).await;
+            }
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "feed",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+            }
+            Err(e) => ctx.reply(&report(e)).await,
+        }
+    }
+}
+
+/// the summary of an entry, or else its content, in html
+fn entry_html(entry: &Entry) -> Option<&str> {
+    entry
+        .summary
+        .as_ref()
+        .map(|t| t.content.as_str())
+        .or_else(|| entry.content.as_ref().and_then(|c| c.body.as_deref()))
+}
+
+/// the placeholders a feed's template may use
+const RSS_FIELDS: [&str; 8] = [
+    "feed_title",
+    "title",
+    "link",
+    "author",
+    "published",
+    "byline",
+    "summary",
+    "tags",
+];
+
+/// title link, then author and time, then the summary
+const RSS_TEMPLATE: &str = "[{title}]({link})\n{byline}\n\n{summary}";
+
+/// the template of a feed, or the default if it has none
+fn rss_template(r: &Rss) -> Template {
+    r.template
+        .as_deref()
+        .and_then(|t| {
+            Template::parse(t, &RSS_FIELDS)
+                .map_err(|e| error!("[{}] template of rss {}: {}", e.label(), r.id, e))
+                .ok()
+        })
+        .unwrap_or_else(|| Template::parse(RSS_TEMPLATE, &RSS_FIELDS).expect("default template"))
+}
+
+/// what the template of a post can show, in plain text
+#[derive(Default)]
+struct PostFields {
+    feed_title: String,
+    title: String,
+    link: String,
+    author: String,
+    published: String,
+    tags: String,
+    summary: String,
+}
+
+impl PostFields {
+    fn new(r: &Rss, entry: &Entry, title: &str, link: &str) -> Self {
+        Self {
+            feed_title: r.title.clone(),
+            title: title.to_owned(),
+            link: link.to_owned(),
+            author: entry
+                .authors
+                .first()
+                .map(|a| a.name.clone())
+                .unwrap_or_default(),
+            published: entry
+                .published
+                .or(entry.updated)
+                .map(|t| {
+                    t.with_timezone(&timezone())
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default(),
+            tags: entry
+                .categories
+                .iter()
+                .map(|c| c.label.as_deref().unwrap_or(&c.term))
+                .collect::<Vec<_>>()
+                .join(", "),
+            summary: entry_html(entry).map(strip_html).unwrap_or_default(),
+        }
+    }
+
+    /// `{summary}` is `summary_len` long, and no summary longer than `max_len`
+    fn render(&self, template: &Template, summary_len: usize, max_len: usize) -> String {
+        template.render(|name, len| match name {
+            "feed_title" => self.feed_title.clone(),
+            "title" => self.title.clone(),
+            "link" => self.link.clone(),
+            "author" => self.author.clone(),
+            "published" => self.published.clone(),
+            "byline" => [&self.author, &self.published]
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" · "),
+            "tags" => self.tags.clone(),
+            "summary" => match len.unwrap_or(summary_len).min(max_len) {
+                0 => String::new(),
+                len => truncate(&self.summary, len),
+            },
+            _ => String::new(),
+        })
+    }
+}
+
+const CAPTION_SUMMARY_LEN: usize = 800;
+const TEXT_SUMMARY_LEN: usize = 3000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum MediaKind {
+    Image,
+    Video,
+    Audio,
+    Document,
+}
+
+/// guess the kind of media from its mime type, or else from the extension
+fn media_kind(url: &str, mime: Option<&str>) -> Option<MediaKind> {
+    let ext = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match (mime.unwrap_or_default(), ext.as_str()) {
+        (m, _) if m.starts_with("image/") => Some(MediaKind::Image),
+        (m, _) if m.starts_with("video/") => Some(MediaKind::Video),
+        (m, _) if m.starts_with("audio/") => Some(MediaKind::Audio),
+        (m, _) if m.contains("flash") || m.contains("html") => None,
+        (m, _) if m.starts_with("application/") => Some(MediaKind::Document),
+        (_, "jpg" | "jpeg" | "png" | "gif" | "webp") => Some(MediaKind::Image),
+        (_, "mp4" | "webm" | "mov") => Some(MediaKind::Video),
+        (_, "mp3" | "m4a" | "ogg" | "opus" | "aac") => Some(MediaKind::Audio),
+        (_, "pdf" | "zip" | "epub") => Some(MediaKind::Document),
+        _ => None,
+    }
+}
+
+/// media attached to the entry, from media rss, enclosures and thumbnails
+fn find_media(entry: &Entry) -> Vec<(MediaKind, String)> {
+    let mut media = vec![];
+    for obj in &entry.media {
+        for content in &obj.content {
+            if let Some(url) = &content.url {
+                let mime = content.content_type.as_ref().map(|m| m.to_string());
+                if let Some(kind) = media_kind(url.as_str(), mime.as_deref()) {
+                    media.push((kind, url.to_string()));
+                }
+            }
+        }
+    }
+    for link in &entry.links {
+        if link.rel.as_deref() == Some("enclosure") {
+            if let Some(kind) = media_kind(&link.href, link.media_type.as_deref()) {
+                media.push((kind, link.href.clone()));
+            }
+        }
+    }
+    // thumbnails stand in for media we can not send, like embedded players
+    if media.is_empty() {
+        for obj in &entry.media {
+            for thumbnail in &obj.thumbnails {
+                media.push((MediaKind::Image, thumbnail.image.uri.clone()));
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    media.retain(|(_, url)| seen.insert(url.clone()));
+    media
+}
+
+/// pick the best way to send the media: a podcast as audio, a video as
+/// video, photos as a photo or an album, anything else as a document
+fn media_content(media: Vec<(MediaKind, String)>, caption: String) -> Content {
+    let first = |kind| {
+        media
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, url)| url.clone())
+    };
+    if let Some(url) = first(MediaKind::Audio) {
+        return Content::Audio { url, caption };
+    }
+    if let Some(url) = first(MediaKind::Video) {
+        return Content::Video { url, caption };
+    }
+    let mut urls = media
+        .iter()
+        .filter(|(k, _)| *k == MediaKind::Image)
+        .map(|(_, url)| url.clone())
+        .collect::<Vec<_>>();
+    // telegram takes at most 10 items in an album
+    urls.truncate(10);
+    match urls.len() {
+        0 => Content::Document {
+            url: media[0].1.clone(),
+            caption,
+        },
+        1 => Content::Photo {
+            url: urls.remove(0),
+            caption,
+        },
+        _ => Content::Album { urls, caption },
+    }
+}
+
+struct Summary {}
+
+#[async_trait]
+impl Callback for Summary {
+    async fn callback(&self, ctx: &Context) {
+        let id = ctx.arg(0).and_then(|t| t.parse::<i32>().ok());
+        let len = match ctx.arg(1) {
+            Some("off") => Some(0),
+            Some(t) => t.parse::<usize>().ok().filter(|&n| n <= TEXT_SUMMARY_LEN),
+            None => None,
+        };
+        let (Some(id), Some(len)) = (id, len) else {
+            ctx.reply("need id and length (up to 3000) or off").await;
+            return;
+        };
+        let reply = match set_rss_summary(&get_conn(), id, len) {
             Ok(n) => {
                 if n > 0 {
                     "done"
@@ -134,88 +705,767 @@ impl Callback for Unsub {
                 }
             }
             Err(e) => {
-                error!("{}", e);
-                "error"
+                ctx.reply(&report(e)).await;
+                return;
             }
         };
-        send(cid, reply).await;
+        ctx.reply(reply).await;
     }
 }
 
-pub fn register(dispatcher: &mut Dispatcher) {
-    dispatcher.register("/rss", Box::new(List {}));
-    dispatcher.register("/sub", Box::new(Sub {}));
-    dispatcher.register("/unsub", Box::new(Unsub {}));
-}
+struct Preview {}
 
-pub async fn rss_monitor_loop() {
-    let cid = std::env::var("MASTER_ID").unwrap();
-    let interval = std::env::var("RSS_INTERVAL").unwrap().parse().unwrap();
-    loop {
-        let conn = get_conn();
-        let rs = list_rss(&conn).unwrap_or_else(|e| {
-            error!("{}", e);
-            vec![]
-        });
-        for r in rs {
-            info!("fetch {}", r.feed);
-            let resp = match reqwest::get(&r.feed).await {
-                Err(e) => {
-                    error!("{}", e);
-                    continue;
+#[async_trait]
+impl Callback for Preview {
+    async fn callback(&self, ctx: &Context) {
+        let id = ctx.arg(0).and_then(|t| t.parse::<i32>().ok());
+        let preview = match ctx.arg(1) {
+            Some("on") => Some(true),
+            Some("off") => Some(false),
+            _ => None,
+        };
+        let (Some(id), Some(preview)) = (id, preview) else {
+            ctx.reply("need id and on or off").await;
+            return;
+        };
+        let reply = match set_rss_preview(&get_conn(), id, preview) {
+            Ok(n) => {
+                if n > 0 {
+                    "done"
+                } else {
+                    "not found"
                 }
-                Ok(resp) => resp,
-            };
-            let bytes = match resp.bytes().await {
-                Err(e) => {
-                    error!("{}", e);
-                    continue;
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        ctx.reply(reply).await;
+    }
+}
+
+struct Updates {}
+
+#[async_trait]
+impl Callback for Updates {
+    async fn callback(&self, ctx: &Context) {
+        let id = ctx.arg(0).and_then(|t| t.parse::<i32>().ok());
+        let updates = match ctx.arg(1) {
+            Some("on") => Some(db::Updates::On),
+            Some("diff") => Some(db::Updates::Diff),
+            Some("off") => Some(db::Updates::Off),
+            _ => None,
+        };
+        let (Some(id), Some(updates)) = (id, updates) else {
+            ctx.reply("need id and on, diff or off").await;
+            return;
+        };
+        let reply = match set_rss_updates(&get_conn(), id, updates) {
+            Ok(n) => {
+                if n > 0 {
+                    "done"
+                } else {
+                    "not found"
                 }
-                Ok(bytes) => bytes,
-            };
-            let feed = match feed_rs::parser::parse(&bytes[..]) {
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        ctx.reply(reply).await;
+    }
+}
+
+/// `<id>` shows the template of a feed, `<id> off` goes back to the
+/// default, anything else after the id is the new template
+struct SetTemplate {}
+
+#[async_trait]
+impl Callback for SetTemplate {
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id, and a template or off").await;
+            return;
+        };
+        let conn = get_conn();
+        let r = match get_rss(&conn, id) {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "feed",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+                return;
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let text = match ctx.rest(1) {
+            "" => {
+                let fields = RSS_FIELDS.map(|f| format!("{{{}}}", f)).join(" ");
+                let template = r.template.as_deref().unwrap_or(RSS_TEMPLATE);
+                let reply = format!("{}\n\nplaceholders: {}", template, fields);
+                ctx.reply(&escape_markdown(&reply)).await;
+                return;
+            }
+            "off" => None,
+            text => match Template::parse(text, &RSS_FIELDS) {
+                Ok(_) => Some(text),
                 Err(e) => {
-                    error!("{}", e);
-                    continue;
+                    ctx.reply(&e.reply()).await;
+                    return;
                 }
-                Ok(feed) => feed,
-            };
+            },
+        };
+        if let Err(e) = set_rss_template(&conn, id, text) {
+            ctx.reply(&report(e)).await;
+            return;
+        }
+        let r = Rss {
+            template: text.map(str::to_owned),
+            ..r
+        };
+        // the latest post stands in for the parts we do not keep
+        let sample = PostFields {
+            feed_title: r.title.clone(),
+            title: r.latest_title.clone(),
+            link: r.latest_link.clone(),
+            author: "Jane Doe".to_owned(),
+            published: chrono::Utc::now()
+                .with_timezone(&timezone())
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            tags: "news, example".to_owned(),
+            summary: "What the post is about, as long as the template or /summary says. "
+                .repeat(20),
+        };
+        ctx.reply("done, a post will look like this:").await;
+        let preview = sample.render(&rss_template(&r), r.summary_len, TEXT_SUMMARY_LEN);
+        ctx.reply(&preview).await;
+    }
+}
 
-            let entries: Vec<(String, String)> = feed
-                .entries
-                .iter()
-                .map(|e| {
-                    (
-                        e.title
-                            .as_ref()
-                            .map(|t| t.content.clone())
-                            .unwrap_or_default(),
-                        e.links.first().map(|l| l.href.clone()).unwrap_or_default(),
-                    )
-                })
-                .collect();
+/// `<id>` shows the keywords of a feed, `<id> all` sends every post again,
+/// anything else after the id is the new keywords
+struct Filter {}
 
-            let cnt = entries
-                .iter()
-                .position(|(a, b)| (a, b) == (&r.latest_title, &r.latest_link))
-                .unwrap_or(1);
-
-            let mut msg = String::new();
-            for (new_title, new_link) in entries.iter().take(cnt) {
-                // update with the first one
-                if msg.is_empty() {
-                    if let Err(e) = update_rss(&conn, r.id, new_title, new_link) {
-                        error!("{}", e);
-                    }
+#[async_trait]
+impl Callback for Filter {
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id, and keywords or all").await;
+            return;
+        };
+        if !ctx.rest(1).is_empty() {
+            set_filter(ctx, id, ctx.rest(1)).await;
+            return;
+        }
+        let reply = match get_rss(&get_conn(), id) {
+            Ok(Some(r)) => match r.keywords {
+                Some(keywords) => escape_markdown(&keywords),
+                None => "all posts".to_owned(),
+            },
+            Ok(None) => MyError::NotFound {
+                what: "feed",
+                key: id.to_string(),
+            }
+            .reply(),
+            Err(e) => report(e),
+        };
+        ctx.reply(&reply).await;
+    }
+}
+
+struct Retry {}
+
+#[async_trait]
+impl Callback for Retry {
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id to retry").await;
+            return;
+        };
+        let conn = get_conn();
+        let r = match get_rss(&conn, id) {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "feed",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+                return;
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        if let Err(e) = reset_health(&conn, "rss", id) {
+            report(MyError::db(e, "rss", id));
+        }
+        drop(conn);
+        let res = check(&r).await;
+        record(
+            Kind::Rss,
+            r.id,
+            &r.title,
+            res.as_ref().map(|(status, _)| *status),
+        )
+        .await;
+        match res {
+            Ok((_, posts)) => {
+                let mut round = [(&r, posts)];
+                dedupe(&mut round);
+                let [(_, posts)] = round;
+                deliver(&r, posts).await;
+                ctx.reply("done, polling again").await;
+            }
+            Err(e) => ctx.reply(&format!("still failing: {}", e.reply())).await,
+        }
+    }
+}
+
+struct History {}
+
+#[async_trait]
+impl Callback for History {
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Rerun
+    }
+
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id").await;
+            return;
+        };
+        let hs = match list_rss_history(&get_conn(), id) {
+            Ok(hs) => hs,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let reply = hs
+            .into_iter()
+            .map(|h| {
+                let time = chrono::DateTime::from_timestamp(h.time, 0)
+                    .map(|t| t.with_timezone(&timezone()).format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                escape_markdown(&format!("{} {}: {} -> {}", time, h.field, h.old, h.new))
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if reply.is_empty() {
+            ctx.reply("no results").await;
+        } else {
+            ctx.reply(&reply).await;
+        }
+    }
+}
+
+/// move a feed past the posts it has now, without delivering them
+pub async fn skip_to_now(id: i32) -> Result<(), MyError> {
+    let r = get_rss(&get_conn(), id)?.ok_or(MyError::NotFound {
+        what: "feed",
+        key: id.to_string(),
+    })?;
+    let (_, posts) = check(&r).await?;
+    info!("skipped {} posts of rss {}", posts.len(), id);
+    Ok(())
+}
+
+/// feed subscriptions, polled every `RSS_INTERVAL` seconds
+pub struct RssModule {}
+
+impl Module for RssModule {
+    fn name(&self) -> &'static str {
+        "rss"
+    }
+
+    fn migrate(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        db::init_rss(conn)
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        dispatcher.register("/rss", Role::Reader, Box::new(List {}));
+        dispatcher.register("/sub", Role::Admin, Box::new(Sub {}));
+        dispatcher.register("/unsub", Role::Admin, Box::new(Unsub {}));
+        dispatcher.register("/summary", Role::Admin, Box::new(Summary {}));
+        dispatcher.register("/preview", Role::Admin, Box::new(Preview {}));
+        dispatcher.register("/updates", Role::Admin, Box::new(Updates {}));
+        dispatcher.register("/template", Role::Admin, Box::new(SetTemplate {}));
+        dispatcher.register("/filter", Role::Admin, Box::new(Filter {}));
+        dispatcher.register("/retry", Role::Admin, Box::new(Retry {}));
+        dispatcher.register("/history", Role::Reader, Box::new(History {}));
+    }
+
+    fn check_settings(&self) -> Result<(), MyError> {
+        config::<u64>("RSS_INTERVAL").map(|_| ())
+    }
+
+    fn task(&self) -> Option<BoxFuture<'static, ()>> {
+        Some(Box::pin(rss_monitor_loop()))
+    }
+}
+
+/// keep the stored feed url, title and home up to date, and tell about changes
+async fn refresh_meta(r: &Rss, feed: &Feed, moved: Option<String>) {
+    let changes = [
+        ("feed", &r.feed, moved),
+        (
+            "title",
+            &r.title,
+            feed.title.as_ref().map(|_| feed_title(feed)),
+        ),
+        ("home", &r.home, feed_home(feed)),
+    ];
+    let mut msg = String::new();
+    for (field, old, new) in changes {
+        let Some(new) = new.filter(|new| new != old && !new.is_empty()) else {
+            continue;
+        };
+        info!("{} of rss {} changed from {} to {}", field, r.id, old, new);
+        if let Err(e) = change_rss(&get_conn(), r.id, field, old, &new) {
+            report(MyError::db(e, "rss", r.id));
+            continue;
+        }
+        let line = match field {
+            "feed" => format!("feed \"{}\" moved to {}", r.title, new),
+            "title" => format!("feed \"{}\" is now called \"{}\"", r.title, new),
+            _ => format!("feed \"{}\" has a new home {}", r.title, new),
+        };
+        msg.push_str(&format!("\n{}", escape_markdown(&line)));
+    }
+    if !msg.is_empty() {
+        notify(Content::Text {
+            text: msg.trim_start().to_owned(),
+            preview: false,
+        })
+        .await;
+    }
+}
+
+/// a new post of a feed, ready to be delivered
+struct Post {
+    title: String,
+    link: String,
+    /// the link without tracking and redirects, to spot the post in other feeds
+    canon: String,
+    /// plain text summary for the served feeds
+    summary: String,
+    /// `None` for a plain title link, those of a feed are sent together
+    content: Option<Content>,
+    /// the other feeds with the same post in this round
+    also_in: Vec<String>,
+}
+
+/// fetch a feed and find what is new since last time, with the http status
+async fn check(r: &Rss) -> Result<(u16, Vec<Post>), MyError> {
+    info!("fetch {}", r.feed);
+    let (resp, moved) = fetch_following(&r.feed).await?;
+    let status = resp.status().as_u16();
+    let bytes = resp.bytes().await?;
+    let feed = parse(&r.feed, &bytes)?;
+    refresh_meta(r, &feed, moved).await;
+
+    let entries: Vec<(String, String, &Entry)> = feed
+        .entries
+        .iter()
+        .map(|e| {
+            let (title, link) = title_link(e);
+            (title, link, e)
+        })
+        .collect();
+
+    // the new entries are the ones before the latest seen, found by what
+    // `find_updates` knows it by, or by its link for a feed that predates it
+    let cnt = entries
+        .iter()
+        .position(|(title, link, e)| match r.latest_key.is_empty() {
+            true => link == &r.latest_link,
+            false => entry_key(title, link, e) == r.latest_key,
+        })
+        .unwrap_or(1);
+
+    let template = rss_template(r);
+    let mut posts = vec![];
+    if r.updates != db::Updates::Off {
+        posts = find_updates(r, &entries, cnt).await;
+    }
+    for (i, (new_title, new_link, entry)) in entries.iter().take(cnt).enumerate() {
+        // update with the first one
+        if i == 0 {
+            let key = entry_key(new_title, new_link, entry);
+            if let Err(e) = update_rss(&get_conn(), r.id, new_title, new_link, key) {
+                report(MyError::db(e, "rss", r.id));
+            }
+        }
+        let summary = entry_html(entry)
+            .map(|html| truncate(&strip_html(html), 1000))
+            .unwrap_or_default();
+        if !wanted(r, new_title, &summary) {
+            info!("filter out post [{}]({})", new_title, new_link);
+            continue;
+        }
+        info!("new post [{}]({})", new_title, new_link);
+        let media = find_media(entry);
+        let fields = PostFields::new(r, entry, new_title, new_link);
+        let content = if !media.is_empty() {
+            // captions are limited to 1024 chars
+            let caption = fields.render(&template, r.summary_len, CAPTION_SUMMARY_LEN);
+            Some(media_content(media, caption))
+        } else if r.summary_len == 0 && !r.preview && r.template.is_none() {
+            None
+        } else {
+            // one message per post, so that each gets its summary and preview
+            let text = fields.render(&template, r.summary_len, TEXT_SUMMARY_LEN);
+            Some(Content::Text {
+                text,
+                preview: r.preview,
+            })
+        };
+        posts.push(Post {
+            title: new_title.clone(),
+            link: new_link.clone(),
+            canon: canonical_url(&resolve_link(new_link).await),
+            summary,
+            content,
+            also_in: vec![],
+        });
+    }
+    Ok((status, posts))
+}
+
+/// a fingerprint of what an entry says, ignoring case, punctuation and spacing
+fn fingerprint(title: &str, summary: &str) -> String {
+    // fnv-1a, stable across builds unlike the std hasher
+    let hash = format!("{} {}", title, summary)
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .flat_map(|w| w.bytes().chain([b' ']))
+        .fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// what changed between two versions of an entry, for humans
+fn entry_diff(old: &RssItem, new: &RssItem) -> String {
+    let mut diff = vec![];
+    if old.title != new.title {
+        diff.push(format!("title: {} → {}", old.title, new.title));
+    }
+    let old_lines = old.summary.lines().collect::<HashSet<_>>();
+    diff.extend(
+        new.summary
+            .lines()
+            .filter(|l| !old_lines.contains(l))
+            .take(3)
+            .map(|l| format!("+ {}", truncate(l, 200))),
+    );
+    escape_markdown(&diff.join("\n"))
+}
+
+/// the entries seen before that changed since, as posts; every entry of the
+/// feed is remembered, the new ones are only announced by `check`
+async fn find_updates(r: &Rss, entries: &[(String, String, &Entry)], cnt: usize) -> Vec<Post> {
+    let start = chrono::Utc::now().timestamp();
+    let mut posts = vec![];
+    for (i, (title, link, entry)) in entries.iter().enumerate() {
+        let key = entry_key(title, link, entry);
+        let summary = entry_html(entry)
+            .map(|html| truncate(&strip_html(html), 1000))
+            .unwrap_or_default();
+        let new = RssItem {
+            title: title.clone(),
+            fingerprint: fingerprint(title, &summary),
+            summary,
+            updated: entry.updated.map(|t| t.timestamp()),
+        };
+        let conn = get_conn();
+        let old = get_rss_item(&conn, r.id, key).unwrap_or_else(|e| {
+            report(MyError::db(e, "rss", r.id));
+            None
+        });
+        if let Err(e) = put_rss_item(&conn, r.id, key, &new) {
+            report(MyError::db(e, "rss", r.id));
+        }
+        drop(conn);
+        let Some(old) = old.filter(|_| i >= cnt) else {
+            continue;
+        };
+        let revised = new.updated.is_some() && new.updated > old.updated;
+        if (old.fingerprint == new.fingerprint && !revised) || !wanted(r, title, &new.summary) {
+            continue;
+        }
+        info!("updated post [{}]({})", title, link);
+        let mut text = format!("Updated: [{}]({})", title, link);
+        if r.updates == db::Updates::Diff {
+            let diff = entry_diff(&old, &new);
+            if !diff.is_empty() {
+                text.push('\n');
+                text.push_str(&diff);
+            }
+        }
+        posts.push(Post {
+            title: format!("Updated: {}", title),
+            link: link.clone(),
+            // the same revision in another feed is the same update
+            canon: format!(
+                "{}#{}",
+                canonical_url(&resolve_link(link).await),
+                new.fingerprint
+            ),
+            summary: new.summary,
+            content: Some(Content::Text {
+                text,
+                preview: false,
+            }),
+            also_in: vec![],
+        });
+    }
+    // entries that left the feed will not change anymore
+    if let Err(e) = prune_rss_items(&get_conn(), r.id, start) {
+        report(MyError::db(e, "rss", r.id));
+    }
+    posts
+}
+
+/// how long a delivered post keeps others from being delivered, in hours
+/// from `DEDUPE_WINDOW`
+fn dedupe_window() -> i64 {
+    setting("DEDUPE_WINDOW", 72)
+}
+
+/// merge a post that several feeds going to the same place have in this
+/// round into the first one, and drop the posts already delivered there
+/// within the window; posts without a link are all kept
+fn dedupe(round: &mut [(&Rss, Vec<Post>)]) {
+    let conn = get_conn();
+    let since = chrono::Utc::now().timestamp() - dedupe_window() * 3600;
+    if let Err(e) = prune_seen(&conn, since) {
+        report(e);
+    }
+    let mut first: HashMap<(String, String), (usize, usize)> = HashMap::new();
+    let mut merged = vec![];
+    for (i, (r, posts)) in round.iter_mut().enumerate() {
+        let dest = destination(Kind::Rss, r.id);
+        let mut kept = 0;
+        posts.retain(|post| {
+            // without a link there is nothing to tell the same post by
+            if post.canon.is_empty() {
+                kept += 1;
+                return true;
+            }
+            let key = (dest.clone(), post.canon.clone());
+            if let Some(&(fi, fj)) = first.get(&key) {
+                if fi != i {
+                    merged.push(((fi, fj), r.title.clone()));
                 }
-                info!("new post [{}]({})", new_title, new_link);
-                msg.push_str(&format!("\n[{}]({})", new_title, new_link));
+                return false;
             }
-            if !msg.is_empty() {
-                send(&cid, msg.trim_start()).await;
+            if seen_since(&conn, &dest, &post.canon, since).unwrap_or_else(|e| {
+                report(MyError::db(e, "rss", r.id));
+                false
+            }) {
+                info!("skip delivered post {}", post.link);
+                return false;
             }
+            first.insert(key, (i, kept));
+            kept += 1;
+            true
+        });
+    }
+    for ((i, j), title) in merged {
+        round[i].1[j].also_in.push(title);
+    }
+}
+
+/// send the posts of a feed, keeping them for the served feeds
+async fn deliver(r: &Rss, posts: Vec<Post>) {
+    let dest = destination(Kind::Rss, r.id);
+    let mut msg = String::new();
+    for post in posts {
+        let conn = get_conn();
+        let seen = match post.canon.is_empty() {
+            true => Ok(0),
+            false => mark_seen(&conn, &dest, &post.canon),
+        };
+        if let Err(e) = seen.and(insert_entry(
+            &conn,
+            "rss",
+            r.id,
+            &r.title,
+            &post.title,
+            &post.link,
+            &post.summary,
+        )) {
+            report(MyError::db(e, "rss", r.id));
         }
         drop(conn);
+        let also_in = match post.also_in.is_empty() {
+            true => String::new(),
+            false => escape_markdown(&format!("also in: {}", post.also_in.join(", "))),
+        };
+        match post.content {
+            None => {
+                msg.push_str(&format!("\n[{}]({})", post.title, post.link));
+                if !also_in.is_empty() {
+                    msg.push_str(&format!(" ({})", also_in));
+                }
+            }
+            Some(mut content) => {
+                if !also_in.is_empty() {
+                    content.push_line(&also_in);
+                }
+                notify_sub(Kind::Rss, r.id, content).await;
+            }
+        }
+    }
+    if !msg.is_empty() {
+        notify_sub(
+            Kind::Rss,
+            r.id,
+            Content::Text {
+                text: msg.trim_start().to_owned(),
+                preview: false,
+            },
+        )
+        .await;
+    }
+}
+
+pub async fn rss_monitor_loop() {
+    let interval: u64 = match config("RSS_INTERVAL") {
+        Ok(interval) => interval,
+        Err(e) => {
+            report(e);
+            return;
+        }
+    };
+    loop {
+        let conn = get_conn();
+        // a feed whose snooze is over catches up from where it stopped
+        if let Err(e) = end_snoozes(&conn, "rss") {
+            report(e);
+        }
+        let rs = list_rss(&conn).unwrap_or_else(|e| {
+            report(e);
+            vec![]
+        });
+        drop(conn);
+        // gather the posts of all feeds first, to spot the same post in several
+        let mut round = vec![];
+        for r in rs.iter().filter(|r| !r.health.paused && !r.pause.held()) {
+            let res = check(r).await;
+            record(
+                Kind::Rss,
+                r.id,
+                &r.title,
+                res.as_ref().map(|(status, _)| *status),
+            )
+            .await;
+            if let Ok((_, posts)) = res {
+                round.push((r, posts));
+            }
+        }
+        dedupe(&mut round);
+        for (r, posts) in round {
+            deliver(r, posts).await;
+        }
         sleep(interval).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_db;
+    use crate::utils::serve_fixtures;
+
+    /// a feed of the fixtures served at `root`, as if subscribed when the
+    /// entry at `latest` was the latest one
+    fn subscribe_fixture(root: &str, file: &str, latest: usize) -> Rss {
+        test_db();
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/rss");
+        let feed = parse(file, &std::fs::read(path.join(file)).unwrap()).unwrap();
+        let entry = &feed.entries[latest];
+        let (title, link) = title_link(entry);
+        let key = entry_key(&title, &link, entry).to_owned();
+        let conn = get_conn();
+        db::init_rss(&conn).unwrap();
+        let url = format!("{}/{}", root, file);
+        insert_rss(&conn, root, file, &url, &title, &link, &key).unwrap();
+        get_rss(&conn, conn.last_insert_rowid() as i32)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn linkless_posts_are_not_deduped() {
+        let r = subscribe_fixture(&serve_fixtures("rss"), "linkless.xml", 2);
+        let (_, posts) = check(&r).await.unwrap();
+        let titles = posts.iter().map(|p| p.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, ["Server is down", "Server is up again"]);
+        let mut round = [(&r, posts)];
+        dedupe(&mut round);
+        let [(_, posts)] = round;
+        assert_eq!(posts.len(), 2);
+        deliver(&r, posts).await;
+
+        // delivered ones keep nothing without a link from being delivered
+        update_rss(
+            &get_conn(),
+            r.id,
+            &r.latest_title,
+            &r.latest_link,
+            &r.latest_key,
+        )
+        .unwrap();
+        let (_, posts) = check(&r).await.unwrap();
+        let mut round = [(&r, posts)];
+        dedupe(&mut round);
+        assert_eq!(round[0].1.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn keywords_filter_posts() {
+        let r = subscribe_fixture(&serve_fixtures("rss"), "linkless.xml", 2);
+        set_rss_keywords(&get_conn(), r.id, Some("SERVER -down")).unwrap();
+        let r = get_rss(&get_conn(), r.id).unwrap().unwrap();
+        let (_, posts) = check(&r).await.unwrap();
+        let titles = posts.iter().map(|p| p.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, ["Server is up again"]);
+        // the latest entry moves on past the ones filtered out
+        let r = get_rss(&get_conn(), r.id).unwrap().unwrap();
+        assert_eq!(r.latest_title, "Server is down");
+    }
+
+    #[tokio::test]
+    async fn retitled_latest_entry_is_an_update() {
+        let root = serve_fixtures("rss");
+        let r = subscribe_fixture(&root, "advisory.xml", 0);
+        set_rss_updates(&get_conn(), r.id, db::Updates::On).unwrap();
+        let r = get_rss(&get_conn(), r.id).unwrap().unwrap();
+        let (_, posts) = check(&r).await.unwrap();
+        assert!(posts.is_empty());
+
+        let r = Rss {
+            feed: format!("{}/advisory_retitled.xml", root),
+            ..r
+        };
+        let (_, posts) = check(&r).await.unwrap();
+        let titles = posts.iter().map(|p| p.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, ["Updated: Advisory 7: fixed in 2.1.1, not 2.1.0"]);
+        let mut round = [(&r, posts)];
+        dedupe(&mut round);
+        assert_eq!(round[0].1.len(), 1);
+    }
+}
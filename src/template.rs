@@ -0,0 +1,247 @@
+use crate::error::MyError;
+use crate::utils::{escape_markdown, truncate};
+
+/// what a placeholder is inside of, which decides how its value is escaped
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Scope {
+    Plain,
+    /// bold, italic or code, until the same char again
+    Entity(char),
+    LinkText,
+    /// right after a link text, a url may follow
+    AfterLink,
+    Url,
+}
+
+impl Scope {
+    /// where a char of the template leads
+    fn next(self, c: char) -> Scope {
+        match (self, c) {
+            (Scope::Plain | Scope::AfterLink, '*' | '_' | '`') => Scope::Entity(c),
+            (Scope::Plain | Scope::AfterLink, '[') => Scope::LinkText,
+            (Scope::Entity(e), c) if c == e => Scope::Plain,
+            (Scope::LinkText, ']') => Scope::AfterLink,
+            (Scope::AfterLink, '(') => Scope::Url,
+            (Scope::AfterLink, _) => Scope::Plain,
+            (Scope::Url, ')') => Scope::Plain,
+            (scope, _) => scope,
+        }
+    }
+
+    /// telegram does not unescape inside an entity, so there the value is
+    /// kept as is, without the char that would end it early
+    fn escape(self, value: &str) -> String {
+        match self {
+            Scope::Plain | Scope::AfterLink => escape_markdown(value),
+            Scope::Entity(e) => value.replace(e, ""),
+            Scope::LinkText => value.replace(']', ""),
+            Scope::Url => value.replace(')', "%29"),
+        }
+    }
+}
+
+enum Part {
+    Text(String),
+    Field {
+        name: String,
+        len: Option<usize>,
+        scope: Scope,
+    },
+}
+
+/// a notification layout in telegram's markdown, with placeholders like
+/// `{title}` or `{summary:200}`, checked and ready to be rendered
+pub struct Template {
+    lines: Vec<Vec<Part>>,
+}
+
+impl Template {
+    /// check the placeholders against the `fields` a subscription has, and
+    /// that the markdown around them is closed; `{{` and `}}` are braces
+    pub fn parse(text: &str, fields: &[&str]) -> Result<Self, MyError> {
+        let err = |reason: String| Err(MyError::Template(reason));
+        let mut lines = vec![];
+        let mut scope = Scope::Plain;
+        for line in text.trim().lines() {
+            let mut parts = vec![];
+            let mut literal = String::new();
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '{' if chars.peek() == Some(&'{') => {
+                        chars.next();
+                        literal.push('{');
+                        scope = scope.next('{');
+                    }
+                    '}' if chars.peek() == Some(&'}') => {
+                        chars.next();
+                        literal.push('}');
+                        scope = scope.next('}');
+                    }
+                    '{' => {
+                        let mut field = String::new();
+                        loop {
+                            match chars.next() {
+                                Some('}') => break,
+                                Some(c) => field.push(c),
+                                None => return err(format!("{{{} is not closed", field)),
+                            }
+                        }
+                        let (name, len) = match field.split_once(':') {
+                            Some((name, len)) => match len.parse::<usize>() {
+                                Ok(len) => (name, Some(len)),
+                                Err(_) => return err(format!("bad length in {{{}}}", field)),
+                            },
+                            None => (field.as_str(), None),
+                        };
+                        if !fields.contains(&name) {
+                            let known = fields
+                                .iter()
+                                .map(|f| format!("{{{}}}", f))
+                                .collect::<Vec<_>>()
+                                .join(" ");
+                            return err(format!("unknown {{{}}}, try {}", name, known));
+                        }
+                        if scope == Scope::AfterLink {
+                            scope = Scope::Plain;
+                        }
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                        parts.push(Part::Field {
+                            name: name.to_owned(),
+                            len,
+                            scope,
+                        });
+                    }
+                    '\\' if matches!(scope, Scope::Plain | Scope::AfterLink) => {
+                        literal.push(c);
+                        if let Some(c) = chars.next() {
+                            literal.push(c);
+                        }
+                        scope = Scope::Plain;
+                    }
+                    c => {
+                        literal.push(c);
+                        scope = scope.next(c);
+                    }
+                }
+            }
+            parts.push(Part::Text(literal));
+            lines.push(parts);
+        }
+        match scope {
+            Scope::Plain | Scope::AfterLink => Ok(Self { lines }),
+            Scope::Entity(e) => err(format!("{} is not closed", e)),
+            Scope::LinkText => err("[ is not closed".to_owned()),
+            Scope::Url => err("( is not closed".to_owned()),
+        }
+    }
+
+    /// fill in the placeholders, `value` gives the plain text of one with
+    /// the length it asks for; a line whose placeholders are all empty is
+    /// left out
+    pub fn render(&self, value: impl Fn(&str, Option<usize>) -> String) -> String {
+        let mut out = vec![];
+        for parts in &self.lines {
+            let mut line = String::new();
+            let mut fields = 0;
+            let mut empty = 0;
+            for part in parts {
+                match part {
+                    Part::Text(text) => line.push_str(text),
+                    Part::Field { name, len, scope } => {
+                        let mut text = value(name, *len);
+                        if let Some(len) = len {
+                            text = truncate(&text, *len);
+                        }
+                        let text = scope.escape(&text);
+                        fields += 1;
+                        if text.is_empty() {
+                            empty += 1;
+                        }
+                        line.push_str(&text);
+                    }
+                }
+            }
+            if fields == 0 || empty < fields {
+                out.push(line);
+            }
+        }
+        out.join("\n").trim().to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: [&str; 4] = ["title", "link", "summary", "author"];
+
+    fn render(template: &str, values: &[(&str, &str)]) -> String {
+        Template::parse(template, &FIELDS)
+            .unwrap()
+            .render(|name, _| {
+                values
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+                    .unwrap_or_default()
+            })
+    }
+
+    #[test]
+    fn unknown_placeholder() {
+        let err = Template::parse("{title} {tags}", &FIELDS).err().unwrap();
+        let MyError::Template(reason) = err else {
+            panic!("not a template error");
+        };
+        assert!(reason.starts_with("unknown {tags}"), "{}", reason);
+    }
+
+    #[test]
+    fn bad_length() {
+        assert!(Template::parse("{summary:many}", &FIELDS).is_err());
+    }
+
+    #[test]
+    fn truncates_to_length() {
+        let text = render("{summary:12}", &[("summary", "one two three four")]);
+        assert_eq!(text, "one two…");
+    }
+
+    #[test]
+    fn escapes_by_scope() {
+        let values = [
+            ("title", "a_b]c*d"),
+            ("link", "https://example.org/x_(y)"),
+            ("author", "x*y_z"),
+        ];
+        // plain text is escaped, link text only loses `]`, a url its `)`
+        assert_eq!(render("{title}", &values), "a\\_b]c\\*d");
+        assert_eq!(
+            render("[{title}]({link})", &values),
+            "[a_bc*d](https://example.org/x_(y%29)"
+        );
+        // inside bold only the closing char goes
+        assert_eq!(render("*{author}*", &values), "*xy_z*");
+        assert_eq!(render("_{author}_", &values), "_x*yz_");
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(render("{{{title}}}", &[("title", "t")]), "{t}");
+    }
+
+    #[test]
+    fn unclosed_markdown() {
+        for template in ["*{title}", "[{title}", "[{title}]({link}", "`x"] {
+            assert!(Template::parse(template, &FIELDS).is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn drops_empty_lines() {
+        let template = "{title}\nby {author}\n\n{summary}";
+        let text = render(template, &[("title", "t"), ("summary", "s")]);
+        assert_eq!(text, "t\n\ns");
+    }
+}
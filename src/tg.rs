@@ -1,36 +1,46 @@
-use crate::dispatcher::Dispatcher;
+use crate::bot::Transport;
+use crate::dispatcher::{Context, Dispatcher, Quoted};
 use crate::error::MyError;
-use log::{error, info};
+use crate::utils::{config, config_or, Content, Target};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
 use reqwest::Client;
 use serde_json::{json, Value};
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::time::Duration;
 
 pub struct Telegram {
+    /// from `TG_API_URL`, a local bot api server instead of api.telegram.org
     prefix: String,
-    pub dispatcher: Dispatcher,
-    master: String,
-    offset: i64,
+    offset: AtomicI64,
+    /// the bot's own user id, known after `getMe`
+    id: AtomicI64,
+    /// how long telegram may hold a `getUpdates` open, `TG_POLL_TIMEOUT`
+    poll_timeout: u64,
+    /// how long any request may take on top of that, `TG_TIMEOUT`
+    timeout: Duration,
 }
 
 impl Telegram {
-    pub fn new() -> Self {
-        let master = std::env::var("MASTER_ID").unwrap();
-        let tg_key = std::env::var("TG_KEY").unwrap();
-        let url = format!("https://api.telegram.org/bot{}/", tg_key);
-        Self {
-            prefix: url,
-            dispatcher: Default::default(),
-            master,
-            offset: 0,
-        }
+    pub fn new() -> Result<Self, MyError> {
+        let key: String = config("TG_KEY")?;
+        let base = config_or("TG_API_URL", "https://api.telegram.org".to_owned())?;
+        let base = base.trim_end_matches('/').to_owned();
+        Ok(Self {
+            prefix: format!("{}/bot{}/", base, key),
+            offset: AtomicI64::new(0),
+            id: AtomicI64::new(0),
+            poll_timeout: config_or("TG_POLL_TIMEOUT", 60)?,
+            timeout: Duration::from_secs(config_or("TG_TIMEOUT", 60)?),
+        })
     }
 
-    pub async fn get(&self) -> Result<Value, MyError> {
+    async fn get(&self) -> Result<Value, MyError> {
         Client::new()
             .post(self.prefix.to_owned() + "getupdates")
-            .timeout(Duration::from_secs(60))
+            .timeout(Duration::from_secs(self.poll_timeout) + self.timeout)
             .header("Content-Type", "application/json")
-            .json(&json!({"offset": self.offset, "timeout": 60}))
+            .json(&json!({"offset": self.offset.load(Ordering::Relaxed), "timeout": self.poll_timeout}))
             .send()
             .await?
             .json()
@@ -38,56 +48,262 @@ impl Telegram {
             .map_err(MyError::Request)
     }
 
-    pub async fn send(&self, id: String, msg: String) -> Result<(), MyError> {
-        let body = json!({
-            "chat_id": id,
-            "text": msg,
-            "parse_mode": "Markdown",
-            "disable_web_page_preview": true
-        });
+    async fn get_me(&self) -> Result<String, MyError> {
+        let resp = self.call("getMe", &json!({})).await?;
+        self.id
+            .store(resp["id"].as_i64().unwrap_or_default(), Ordering::Relaxed);
+        match resp["username"].as_str() {
+            Some(username) => {
+                info!("i am @{}", username);
+                Ok(username.to_owned())
+            }
+            None => Err(MyError::Telegram {
+                method: "getMe".to_owned(),
+                code: 0,
+                description: format!("no username in {}", resp),
+            }),
+        }
+    }
+
+    /// call a bot api method, the result if telegram says ok
+    async fn call(&self, method: &str, body: &Value) -> Result<Value, MyError> {
         let resp = Client::new()
-            .post(self.prefix.to_owned() + "sendMessage")
-            .timeout(std::time::Duration::from_secs(60))
+            .post(self.prefix.to_owned() + method)
+            .timeout(self.timeout)
             .header("Content-Type", "application/json")
-            .json(&body)
+            .json(body)
             .send()
             .await?
             .json::<Value>()
             .await?;
         match resp["ok"] {
-            Value::Bool(true) => Ok(()),
-            _ => Err(MyError::Custom(resp.to_string())),
+            Value::Bool(true) => Ok(resp["result"].clone()),
+            _ => Err(MyError::Telegram {
+                method: method.to_owned(),
+                code: resp["error_code"].as_i64().unwrap_or_default(),
+                description: resp["description"].as_str().unwrap_or_default().to_owned(),
+            }),
         }
     }
 
-    pub async fn process(&mut self, json: Value) {
+    async fn send_content(&self, target: &Target, content: &Content) -> Result<(), MyError> {
+        let id = &target.chat;
+        let media = |method, field: &str, url: &String, caption: &String| {
+            let body = json!({
+                "chat_id": id,
+                field: url,
+                "caption": caption,
+                "parse_mode": "Markdown"
+            });
+            (method, body)
+        };
+        let (method, mut body) = match content {
+            Content::Text { text, preview } => (
+                "sendMessage",
+                json!({
+                    "chat_id": id,
+                    "text": text,
+                    "parse_mode": "Markdown",
+                    "disable_web_page_preview": !preview
+                }),
+            ),
+            Content::Choice { text, buttons } => {
+                let keyboard = buttons
+                    .iter()
+                    .map(|(label, cmd)| vec![json!({"text": label, "callback_data": cmd})])
+                    .collect::<Vec<_>>();
+                (
+                    "sendMessage",
+                    json!({
+                        "chat_id": id,
+                        "text": text,
+                        "parse_mode": "Markdown",
+                        "disable_web_page_preview": true,
+                        "reply_markup": {"inline_keyboard": keyboard}
+                    }),
+                )
+            }
+            Content::Photo { url, caption } => media("sendPhoto", "photo", url, caption),
+            Content::Video { url, caption } => media("sendVideo", "video", url, caption),
+            Content::Audio { url, caption } => media("sendAudio", "audio", url, caption),
+            Content::Document { url, caption } => media("sendDocument", "document", url, caption),
+            Content::Album { urls, caption } => {
+                // the caption of the first item is shown for the whole album
+                let items = urls
+                    .iter()
+                    .enumerate()
+                    .map(|(i, url)| match i {
+                        0 => json!({
+                            "type": "photo",
+                            "media": url,
+                            "caption": caption,
+                            "parse_mode": "Markdown"
+                        }),
+                        _ => json!({"type": "photo", "media": url}),
+                    })
+                    .collect::<Vec<_>>();
+                ("sendMediaGroup", json!({"chat_id": id, "media": items}))
+            }
+        };
+        if let Some(thread) = target.thread {
+            body["message_thread_id"] = json!(thread);
+        }
+        if let Some(message_id) = target.reply_to {
+            body["reply_parameters"] =
+                json!({"message_id": message_id, "allow_sending_without_reply": true});
+        }
+        self.call(method, &body).await.map(|_| ())
+    }
+
+    async fn send_or_fallback(&self, target: Target, content: Content) -> Result<(), MyError> {
+        match self.send_content(&target, &content).await {
+            Err(e) => match content.fallback() {
+                Some(fallback) => {
+                    warn!("send media error: {}, fall back to text", e);
+                    self.send_content(&target, &fallback).await
+                }
+                None => Err(e),
+            },
+            ok => ok,
+        }
+    }
+
+    /// a tap on one of our buttons runs the command it carries
+    async fn process_button(&self, dispatcher: &Dispatcher, q: &Value) {
+        let answer = json!({"callback_query_id": q["id"]});
+        if let Err(e) = self.call("answerCallbackQuery", &answer).await {
+            error!("answer callback error: {}", e);
+        }
+        let (Value::Number(cid), Value::Number(uid), Value::String(data)) =
+            (&q["message"]["chat"]["id"], &q["from"]["id"], &q["data"])
+        else {
+            return;
+        };
+        debug!("tg button {}", data);
+        let ctx = Context {
+            // the message with the button
+            message_id: q["message"]["message_id"].as_i64(),
+            thread: thread(&q["message"]),
+            private: q["message"]["chat"]["type"].as_str() == Some("private"),
+            ..Context::new(&cid.to_string(), &uid.to_string(), data)
+        };
+        dispatcher.dispatch(&ctx).await;
+    }
+
+    async fn process(&self, dispatcher: &Dispatcher, json: Value) {
         if !json["ok"].as_bool().unwrap_or(false) {
             error!("polling error: {:?}", json["description"]);
         }
         for m in json["result"].as_array().unwrap_or(&vec![]) {
             let new_offset = m["update_id"].as_i64().unwrap_or(0);
-            self.offset = i64::max(self.offset, new_offset + 1);
+            self.offset.fetch_max(new_offset + 1, Ordering::Relaxed);
             if !m["inline_query"].is_null() || !m["chosen_inline_result"].is_null() {
                 continue;
             }
-            let m = match m["message"]
-                .as_object()
-                .or_else(|| m["edited_message"].as_object())
-            {
-                Some(m) => m,
+            if m["callback_query"].is_object() {
+                self.process_button(dispatcher, &m["callback_query"]).await;
+                continue;
+            }
+            // a missing key is null for a value, but a panic for a map
+            let (m, edited) = match m {
+                m if m["message"].is_object() => (&m["message"], false),
+                m if m["edited_message"].is_object() => (&m["edited_message"], true),
+                m if m["channel_post"].is_object() => (&m["channel_post"], false),
+                m if m["edited_channel_post"].is_object() => (&m["edited_channel_post"], true),
                 _ => continue,
             };
             let cid = match &m["chat"]["id"] {
                 Value::Number(cid) => cid.to_string(),
                 _ => continue,
             };
-            if cid != self.master {
+            // a channel post has no sender, the channel speaks for itself
+            let uid = match &m["from"]["id"] {
+                Value::Number(uid) => uid.to_string(),
+                _ if m["chat"]["type"].as_str() == Some("channel") => cid.clone(),
+                _ => continue,
+            };
+            // files come with a caption instead of a text
+            let Some(text) = m["text"].as_str().or(m["caption"].as_str()) else {
                 continue;
+            };
+            debug!("tg recv {}", text);
+            let r = &m["reply_to_message"];
+            let reply_to = r.is_object().then(|| Quoted {
+                message_id: r["message_id"].as_i64().unwrap_or_default(),
+                uid: r["from"]["id"]
+                    .as_i64()
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+                text: r["text"]
+                    .as_str()
+                    .or(r["caption"].as_str())
+                    .unwrap_or_default()
+                    .to_owned(),
+            });
+            let ctx = Context {
+                message_id: m["message_id"].as_i64(),
+                thread: thread(m),
+                date: m["date"].as_i64().unwrap_or_default(),
+                edited,
+                private: m["chat"]["type"].as_str() == Some("private"),
+                reply_to,
+                ..Context::new(&cid, &uid, text)
+            };
+            dispatcher.dispatch(&ctx).await;
+        }
+    }
+}
+
+/// the forum topic of a message, replies in other groups have a thread too
+fn thread(m: &Value) -> Option<i64> {
+    match m["is_topic_message"].as_bool() {
+        Some(true) => m["message_thread_id"].as_i64(),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl Transport for Telegram {
+    async fn start(&self) -> Result<String, MyError> {
+        self.get_me().await
+    }
+
+    async fn poll(&self, dispatcher: &Dispatcher) -> Result<(), MyError> {
+        let json = self.get().await?;
+        self.process(dispatcher, json).await;
+        Ok(())
+    }
+
+    async fn send(&self, target: Target, content: Content) -> Result<(), MyError> {
+        self.send_or_fallback(target, content).await
+    }
+
+    async fn can_post(&self, chat: &str) -> Result<String, MyError> {
+        let info = self.call("getChat", &json!({"chat_id": chat})).await?;
+        let Some(id) = info["id"].as_i64() else {
+            return Err(MyError::NotFound {
+                what: "chat",
+                key: chat.to_owned(),
+            });
+        };
+        let me = json!({"chat_id": id, "user_id": self.id.load(Ordering::Relaxed)});
+        let member = self.call("getChatMember", &me).await?;
+        let channel = info["type"].as_str() == Some("channel");
+        let allowed = match member["status"].as_str() {
+            Some("creator") => true,
+            // only admins post in a channel, and only with the right to
+            Some("administrator") => {
+                !channel || member["can_post_messages"].as_bool() == Some(true)
             }
-            if let Value::String(text) = &m["text"] {
-                info!("tg recv {}", text);
-                self.dispatcher.dispatch(&cid, text).await;
-            }
+            Some("member") => !channel,
+            Some("restricted") => member["can_send_messages"].as_bool() == Some(true),
+            _ => false,
+        };
+        match allowed {
+            true => Ok(id.to_string()),
+            false => Err(MyError::CanNotPost {
+                chat: chat.to_owned(),
+            }),
         }
     }
 }
@@ -1,25 +1,443 @@
+use crate::error::{report, MyError};
 use std::sync::Arc;
 use std::sync::LazyLock;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::sync::Mutex;
 
-type Message = (String, String);
+/// what to send to a chat
+#[derive(Clone, Debug)]
+pub enum Content {
+    Text {
+        text: String,
+        preview: bool,
+    },
+    /// text with a button for each (label, command) to run on a tap
+    Choice {
+        text: String,
+        buttons: Vec<(String, String)>,
+    },
+    Photo {
+        url: String,
+        caption: String,
+    },
+    /// several photos in one message
+    Album {
+        urls: Vec<String>,
+        caption: String,
+    },
+    Video {
+        url: String,
+        caption: String,
+    },
+    Audio {
+        url: String,
+        caption: String,
+    },
+    Document {
+        url: String,
+        caption: String,
+    },
+}
+
+impl Content {
+    /// the text to send instead when the media can not be sent
+    pub fn fallback(&self) -> Option<Content> {
+        let (url, caption) = match self {
+            Content::Text { .. } | Content::Choice { .. } => return None,
+            Content::Album { urls, caption } => (urls.first()?, caption),
+            Content::Photo { url, caption }
+            | Content::Video { url, caption }
+            | Content::Audio { url, caption }
+            | Content::Document { url, caption } => (url, caption),
+        };
+        Some(Content::Text {
+            text: format!("{}\n[media]({})", caption, url),
+            preview: true,
+        })
+    }
+
+    /// add a line to the text, or to the caption of media
+    pub fn push_line(&mut self, line: &str) {
+        let text = match self {
+            Content::Text { text, .. } | Content::Choice { text, .. } => text,
+            Content::Photo { caption, .. }
+            | Content::Album { caption, .. }
+            | Content::Video { caption, .. }
+            | Content::Audio { caption, .. }
+            | Content::Document { caption, .. } => caption,
+        };
+        text.push('\n');
+        text.push_str(line);
+    }
+}
 
-static CHANNEL: LazyLock<(Sender<Message>, Arc<Mutex<Receiver<Message>>>)> = LazyLock::new(|| {
+/// where and how to send something
+#[derive(Clone, Debug, Default)]
+pub struct Target {
+    pub chat: String,
+    /// the topic of a forum supergroup
+    pub thread: Option<i64>,
+    /// answer this message
+    pub reply_to: Option<i64>,
+}
+
+impl Target {
+    pub fn chat(id: &str) -> Self {
+        Self {
+            chat: id.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+type Message = (Target, Content);
+type Channel = (Sender<Message>, Arc<Mutex<Receiver<Message>>>);
+
+static CHANNEL: LazyLock<Channel> = LazyLock::new(|| {
     let (tx, rx) = mpsc::channel(8);
     (tx, Arc::new(Mutex::new(rx)))
 });
 
 pub async fn send(id: &str, msg: &str) {
-    if let Err(e) = CHANNEL.0.send((id.to_owned(), msg.to_owned())).await {
+    let content = Content::Text {
+        text: msg.to_owned(),
+        preview: false,
+    };
+    send_content(id, content).await;
+}
+
+pub async fn send_content(id: &str, content: Content) {
+    send_to(Target::chat(id), content).await;
+}
+
+pub async fn send_to(target: Target, content: Content) {
+    if let Err(e) = CHANNEL.0.send((target, content)).await {
         log::error!("channel send error {e}");
     };
 }
 
-pub async fn recv() -> Option<(String, String)> {
+pub async fn recv() -> Option<Message> {
     CHANNEL.1.lock().await.recv().await
 }
 
+/// get a url, a status other than success is an error
+pub async fn fetch(url: &str) -> Result<reqwest::Response, MyError> {
+    let resp = reqwest::get(url).await?;
+    if !resp.status().is_success() {
+        return Err(MyError::Status {
+            url: url.to_owned(),
+            status: resp.status().as_u16(),
+        });
+    }
+    Ok(resp)
+}
+
+/// like `fetch`, but follows redirects by hand to also return where the url
+/// has moved for good, if every redirect on the way is permanent
+pub async fn fetch_following(url: &str) -> Result<(reqwest::Response, Option<String>), MyError> {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let mut url = url::Url::parse(url)?;
+    let mut moved = None;
+    let mut permanent = true;
+    for _ in 0..10 {
+        let resp = client.get(url.clone()).send().await?;
+        let status = resp.status();
+        if !status.is_redirection() {
+            if !status.is_success() {
+                return Err(MyError::Status {
+                    url: url.to_string(),
+                    status: status.as_u16(),
+                });
+            }
+            return Ok((resp, moved));
+        }
+        let Some(location) = resp
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+        else {
+            return Err(MyError::Status {
+                url: url.to_string(),
+                status: status.as_u16(),
+            });
+        };
+        url = url.join(location)?;
+        permanent = permanent && matches!(status.as_u16(), 301 | 308);
+        if permanent {
+            moved = Some(url.to_string());
+        }
+    }
+    Err(MyError::Redirects {
+        url: url.to_string(),
+    })
+}
+
+/// a setting from the environment, parsed, an error if it is not set
+pub fn config<T: std::str::FromStr>(name: &str) -> Result<T, MyError> {
+    let value = std::env::var(name).map_err(|e| MyError::Config {
+        name: name.to_owned(),
+        reason: e.to_string(),
+    })?;
+    value.parse().map_err(|_| MyError::Config {
+        name: name.to_owned(),
+        reason: format!("can not parse {:?}", value),
+    })
+}
+
+/// like `config`, but `default` if it is not set
+pub fn config_or<T: std::str::FromStr>(name: &str, default: T) -> Result<T, MyError> {
+    match std::env::var(name) {
+        Ok(_) => config(name),
+        Err(_) => Ok(default),
+    }
+}
+
+/// like `config_or`, where an error can not be returned: a bad value is
+/// reported and `default` used, though `check_settings` keeps the bot from
+/// starting with one
+pub fn setting<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(_) => config(name).unwrap_or_else(|e| {
+            report(e);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// the settings read with `setting`, checked before the bot starts
+pub fn check_settings() -> Result<(), MyError> {
+    config_or("TIMEZONE", chrono_tz::UTC)?;
+    config_or("MAX_FAILURES", 0u32)?;
+    config_or("DEDUPE_WINDOW", 0i64)?;
+    config_or("EDIT_WINDOW", 0i64)?;
+    config_or("DIALOGUE_TIMEOUT", 0i64)?;
+    Ok(())
+}
+
 pub async fn sleep(n: u64) {
     tokio::time::sleep(std::time::Duration::from_secs(n)).await;
 }
+
+/// a duration like `90m`, `12h`, `3d` or `2w`, in seconds
+pub fn parse_duration(text: &str) -> Option<i64> {
+    let (i, _) = text.char_indices().last()?;
+    let (n, unit) = text.split_at(i);
+    let unit = match unit {
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return None,
+    };
+    n.parse::<i64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .and_then(|n| n.checked_mul(unit))
+}
+
+/// the timezone to show times in, from `TIMEZONE`
+pub fn timezone() -> chrono_tz::Tz {
+    setting("TIMEZONE", chrono_tz::UTC)
+}
+
+/// the same for http and https, with or without `www.`, a trailing slash or
+/// a fragment, to tell whether two urls point to the same thing
+pub fn canonical_url(url: &str) -> String {
+    let Ok(url) = url::Url::parse(url.trim()) else {
+        return url.trim().to_lowercase();
+    };
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    // `port` is none for the default port of the scheme
+    let port = url.port().map(|p| format!(":{}", p)).unwrap_or_default();
+    let path = url.path().trim_end_matches('/');
+    let pairs = url
+        .query_pairs()
+        .filter(|(k, _)| !is_tracking_param(k))
+        .collect::<Vec<_>>();
+    let query = match pairs.is_empty() {
+        true => String::new(),
+        false => {
+            let mut query = url::form_urlencoded::Serializer::new("?".to_owned());
+            query.extend_pairs(pairs);
+            query.finish()
+        }
+    };
+    format!("{}{}{}{}", host, port, path, query)
+}
+
+/// query parameters that only tell where a visitor came from
+fn is_tracking_param(key: &str) -> bool {
+    key.starts_with("utm_")
+        || matches!(
+            key,
+            "fbclid" | "gclid" | "yclid" | "igshid" | "mc_cid" | "mc_eid" | "_hsenc" | "_hsmi"
+        )
+}
+
+/// where a link of a redirecting feed proxy like feedburner really points to
+pub async fn resolve_link(link: &str) -> String {
+    let proxied = url::Url::parse(link).is_ok_and(|url| {
+        matches!(
+            url.host_str(),
+            Some("feedproxy.google.com" | "feeds.feedburner.com")
+        )
+    });
+    if !proxied {
+        return link.to_owned();
+    }
+    match reqwest::get(link).await {
+        Ok(resp) => resp.url().to_string(),
+        Err(e) => {
+            log::warn!("resolve {} error: {}", link, e);
+            link.to_owned()
+        }
+    }
+}
+
+/// `owner/repo` from a repo name or its github url, in lower case
+pub fn canonical_repo(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    let name = name
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_start_matches("www.")
+        .trim_start_matches("github.com/")
+        .trim_end_matches('/');
+    name.strip_suffix(".git").unwrap_or(name).to_owned()
+}
+
+/// turn a piece of html into plain text, keeping paragraphs apart
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim_start_matches('/');
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if matches!(
+            name.as_str(),
+            "br" | "p" | "div" | "li" | "tr" | "h1" | "h2" | "h3"
+        ) {
+            text.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    let text = decode_entities(&text);
+    text.lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut res = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                e => e
+                    .strip_prefix("#x")
+                    .map(|n| u32::from_str_radix(n, 16))
+                    .or_else(|| e.strip_prefix('#').map(|n| n.parse()))
+                    .and_then(|n| n.ok())
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                res.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// cut the text to at most `len` chars, on a word boundary if possible
+pub fn truncate(text: &str, len: usize) -> String {
+    if text.chars().count() <= len {
+        return text.to_owned();
+    }
+    let cut: String = text.chars().take(len.saturating_sub(1)).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(pos) if pos > 0 => &cut[..pos],
+        _ => &cut,
+    };
+    format!("{}…", cut.trim_end())
+}
+
+/// escape the characters that mean something in telegram's markdown
+pub fn escape_markdown(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '_' | '*' | '`' | '[') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// serve the files of `tests/fixtures/<dir>` on a free local port, for as
+/// long as the tests run
+#[cfg(all(test, any(feature = "rss", feature = "page")))]
+pub(crate) fn serve_fixtures(dir: &str) -> String {
+    use std::io::{BufRead, BufReader, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(dir);
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = String::new();
+            let mut reader = BufReader::new(&stream);
+            reader.read_line(&mut request).unwrap();
+            let mut header = String::new();
+            while reader.read_line(&mut header).unwrap_or(0) > 2 {
+                header.clear();
+            }
+            let path = request.split_whitespace().nth(1).unwrap_or("/");
+            let (status, body) = match std::fs::read(dir.join(path.trim_start_matches('/'))) {
+                Ok(body) => ("200 OK", body),
+                Err(_) => ("404 Not Found", vec![]),
+            };
+            let head = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&body);
+        }
+    });
+    format!("http://{}", addr)
+}
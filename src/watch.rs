@@ -0,0 +1,414 @@
+use crate::acl::Role;
+use crate::bot::Module;
+use crate::db::{
+    self, delete_watch, end_snoozes, find_watch, get_conn, get_watch, insert_entry, insert_watch,
+    insert_watch_seen, list_watch, reset_health, watch_seen, Watch,
+};
+use crate::dispatcher::{Callback, Context, Dispatcher, OnEdit};
+use crate::error::{report, MyError};
+use crate::health::{record, Kind};
+use crate::pause::describe;
+use crate::route::notify_sub;
+use crate::template::Template;
+use crate::utils::{canonical_repo, config_or, escape_markdown, sleep, Content};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use log::info;
+use rusqlite::Connection;
+
+const USAGE: &str = "need a repo, issues or prs, and search qualifiers, \
+like owner/repo issues label:security or owner/repo prs is:merged";
+
+/// how many of the latest matches a search looks at
+const PER_PAGE: &str = "50";
+
+const FIELDS: [&str; 6] = ["repo", "number", "title", "link", "author", "labels"];
+
+const WATCH_TEMPLATE: &str = "[{title}]({link})\n{repo} #{number} by {author}\n{labels}";
+
+/// an issue or pull request a search found
+struct Item {
+    number: i64,
+    title: String,
+    link: String,
+    author: String,
+    labels: Vec<String>,
+    /// when it started to match, see `closing`
+    time: i64,
+}
+
+impl Item {
+    fn from_json(v: &serde_json::Value, closing: bool) -> Option<Self> {
+        let labels = v["labels"]
+            .as_array()
+            .map(|ls| {
+                ls.iter()
+                    .filter_map(|l| l["name"].as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            number: v["number"].as_i64()?,
+            title: v["title"].as_str()?.to_owned(),
+            link: v["html_url"].as_str()?.to_owned(),
+            author: v["user"]["login"].as_str().unwrap_or_default().to_owned(),
+            labels,
+            time: parse_time(&v[if closing { "closed_at" } else { "created_at" }]),
+        })
+    }
+}
+
+fn parse_time(v: &serde_json::Value) -> i64 {
+    v.as_str()
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.timestamp())
+        .unwrap_or_default()
+}
+
+/// whether a query matches once something is merged or closed, so an old
+/// item may turn up new, rather than once it is opened
+fn closing(query: &str) -> bool {
+    query
+        .split_whitespace()
+        .any(|q| matches!(q, "is:merged" | "is:closed"))
+}
+
+/// `issues` or `prs`, from what a user may type
+fn parse_kind(word: &str) -> Option<&'static str> {
+    match word.to_lowercase().as_str() {
+        "issue" | "issues" => Some("issues"),
+        "pr" | "prs" | "pull" | "pulls" => Some("prs"),
+        _ => None,
+    }
+}
+
+/// the latest issues or pull requests of `repo` matching `query`, the ones
+/// opened last first, or the ones updated last for a closing query, from
+/// the search api of `GITHUB_API_URL`; `GITHUB_TOKEN` raises the
+/// rate limit
+async fn search(repo: &str, kind: &str, query: &str) -> Result<(u16, Vec<Item>), MyError> {
+    let base = config_or("GITHUB_API_URL", "https://api.github.com".to_owned())?;
+    let is = if kind == "prs" { "pr" } else { "issue" };
+    let q = format!("repo:{} is:{} {}", repo, is, query);
+    let closing = closing(query);
+    let url = url::Url::parse_with_params(
+        &format!("{}/search/issues", base.trim_end_matches('/')),
+        &[
+            ("q", q.trim()),
+            ("sort", if closing { "updated" } else { "created" }),
+            ("order", "desc"),
+            ("per_page", PER_PAGE),
+        ],
+    )?;
+    let mut req = reqwest::Client::new()
+        .get(url.as_str())
+        .header(reqwest::header::USER_AGENT, "turtlebot")
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json");
+    let token: String = config_or("GITHUB_TOKEN", String::new())?;
+    if !token.is_empty() {
+        req = req.bearer_auth(token);
+    }
+    let resp = req.send().await?;
+    let status = resp.status().as_u16();
+    if !resp.status().is_success() {
+        return Err(MyError::Status {
+            url: url.to_string(),
+            status,
+        });
+    }
+    let json: serde_json::Value = resp.json().await?;
+    let items = json["items"]
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|v| Item::from_json(v, closing))
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((status, items))
+}
+
+fn format_item(w: &Watch, item: &Item) -> String {
+    let template = Template::parse(WATCH_TEMPLATE, &FIELDS).expect("default template");
+    template.render(|name, _| match name {
+        "repo" => w.repo.clone(),
+        "number" => item.number.to_string(),
+        "title" => item.title.clone(),
+        "link" => item.link.clone(),
+        "author" => item.author.clone(),
+        "labels" => item.labels.join(", "),
+        _ => String::new(),
+    })
+}
+
+/// take what the search finds now as seen, without telling about it
+fn mark_seen(conn: &Connection, id: i32, items: &[Item]) -> Result<(), MyError> {
+    for item in items {
+        insert_watch_seen(conn, id, item.number)?;
+    }
+    Ok(())
+}
+
+struct List {}
+
+#[async_trait]
+impl Callback for List {
+    fn on_edit(&self) -> OnEdit {
+        OnEdit::Rerun
+    }
+
+    async fn callback(&self, ctx: &Context) {
+        let ws = match list_watch(&get_conn()) {
+            Ok(ws) => ws,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let reply = ws
+            .into_iter()
+            .map(|w| {
+                format!(
+                    "{0} [{1}](https://github.com/{1}) {2} {3}{4}",
+                    w.id,
+                    w.repo,
+                    w.kind,
+                    escape_markdown(&w.query),
+                    describe(&w.pause)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if reply.is_empty() {
+            ctx.reply("no results").await;
+        } else {
+            ctx.reply(&reply).await;
+        }
+    }
+}
+
+/// `<repo> <issues|prs> [qualifiers]`, only what turns up after this is told
+struct Add {}
+
+#[async_trait]
+impl Callback for Add {
+    async fn callback(&self, ctx: &Context) {
+        let (Some(repo), Some(kind)) = (ctx.arg(0), ctx.arg(1).and_then(parse_kind)) else {
+            ctx.reply(USAGE).await;
+            return;
+        };
+        let repo = canonical_repo(repo);
+        if repo.split('/').count() != 2 {
+            ctx.reply(USAGE).await;
+            return;
+        }
+        let query = ctx.rest(2).split_whitespace().collect::<Vec<_>>().join(" ");
+        match find_watch(&get_conn(), &repo, kind, &query) {
+            Ok(Some(id)) => {
+                ctx.reply(&format!("already watching as #{}", id)).await;
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                report(e);
+            }
+        }
+        // also checks that github takes the query
+        let items = match search(&repo, kind, &query).await {
+            Ok((_, items)) => items,
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        let conn = get_conn();
+        let res = insert_watch(&conn, &repo, kind, &query)
+            .map_err(MyError::from)
+            .and_then(|id| mark_seen(&conn, id, &items).map(|_| id));
+        let reply = match res {
+            Ok(id) => format!("OK, watching as #{}, {} found so far", id, items.len()),
+            Err(e) => report(e),
+        };
+        ctx.reply(&reply).await;
+    }
+}
+
+struct Unwatch {}
+
+#[async_trait]
+impl Callback for Unwatch {
+    async fn callback(&self, ctx: &Context) {
+        if ctx.step() == Some("confirm") {
+            let Some(id) = ctx.confirmed().and_then(|id| id.parse::<i32>().ok()) else {
+                ctx.reply("cancelled").await;
+                return;
+            };
+            let reply = match delete_watch(&get_conn(), id) {
+                Ok(0) => "not found",
+                Ok(_) => "done",
+                Err(e) => {
+                    ctx.reply(&report(e)).await;
+                    return;
+                }
+            };
+            ctx.reply(reply).await;
+            return;
+        }
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id to del").await;
+            return;
+        };
+        match get_watch(&get_conn(), id) {
+            Ok(Some(w)) => {
+                let what = format!("{} {} {}", w.repo, w.kind, w.query);
+                let question = format!(
+                    "stop watching #{} \"{}\"?",
+                    id,
+                    escape_markdown(what.trim())
+                );
+                ctx.ask_confirm(&question, &id.to_string()).await;
+            }
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "watch",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+            }
+            Err(e) => ctx.reply(&report(e)).await,
+        }
+    }
+}
+
+/// for `/wresume <id> skip`, see `mark_seen`
+pub async fn skip_to_now(id: i32) -> Result<(), MyError> {
+    let w = get_watch(&get_conn(), id)?.ok_or(MyError::NotFound {
+        what: "watch",
+        key: id.to_string(),
+    })?;
+    let (_, items) = search(&w.repo, &w.kind, &w.query).await?;
+    mark_seen(&get_conn(), w.id, &items)
+}
+
+struct Retry {}
+
+#[async_trait]
+impl Callback for Retry {
+    async fn callback(&self, ctx: &Context) {
+        let Some(id) = ctx.arg(0).and_then(|t| t.parse::<i32>().ok()) else {
+            ctx.reply("need id to retry").await;
+            return;
+        };
+        let conn = get_conn();
+        let w = match get_watch(&conn, id) {
+            Ok(Some(w)) => w,
+            Ok(None) => {
+                let e = MyError::NotFound {
+                    what: "watch",
+                    key: id.to_string(),
+                };
+                ctx.reply(&e.reply()).await;
+                return;
+            }
+            Err(e) => {
+                ctx.reply(&report(e)).await;
+                return;
+            }
+        };
+        if let Err(e) = reset_health(&conn, "watch", id) {
+            report(MyError::db(e, "watch", id));
+        }
+        drop(conn);
+        let res = check(&w).await;
+        record(Kind::Watch, w.id, &w.repo, res.as_ref().copied()).await;
+        match res {
+            Ok(_) => ctx.reply("done, polling again").await,
+            Err(e) => ctx.reply(&format!("still failing: {}", e.reply())).await,
+        }
+    }
+}
+
+/// github issue and pull request searches, polled every `WATCH_INTERVAL`
+/// seconds
+pub struct WatchModule {}
+
+impl Module for WatchModule {
+    fn name(&self) -> &'static str {
+        "watch"
+    }
+
+    fn migrate(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        db::init_watch(conn)
+    }
+
+    fn register(&self, dispatcher: &mut Dispatcher) {
+        dispatcher.register("/watches", Role::Reader, Box::new(List {}));
+        dispatcher.register("/watch", Role::Admin, Box::new(Add {}));
+        dispatcher.register("/unwatch", Role::Admin, Box::new(Unwatch {}));
+        dispatcher.register("/wretry", Role::Admin, Box::new(Retry {}));
+    }
+
+    fn check_settings(&self) -> Result<(), MyError> {
+        config_or("WATCH_INTERVAL", 900u64).map(|_| ())
+    }
+
+    fn task(&self) -> Option<BoxFuture<'static, ()>> {
+        Some(Box::pin(watch_loop()))
+    }
+}
+
+/// run the search and tell about each match not seen before, oldest first,
+/// leaving out those that matched before the watch was added
+async fn check(w: &Watch) -> Result<u16, MyError> {
+    info!("search watch {} {} {}", w.id, w.repo, w.kind);
+    let (status, items) = search(&w.repo, &w.kind, &w.query).await?;
+    for item in items.iter().rev() {
+        if item.time < w.added || watch_seen(&get_conn(), w.id, item.number)? {
+            continue;
+        }
+        let title = format!("{} #{} {}", w.repo, item.number, item.title);
+        let conn = get_conn();
+        if let Err(e) = insert_entry(&conn, "watch", w.id, &w.repo, &title, &item.link, "") {
+            report(MyError::db(e, "watch", w.id));
+        }
+        let text = format_item(w, item);
+        notify_sub(
+            Kind::Watch,
+            w.id,
+            Content::Text {
+                text,
+                preview: false,
+            },
+        )
+        .await;
+        insert_watch_seen(&get_conn(), w.id, item.number)?;
+    }
+    Ok(status)
+}
+
+async fn watch_loop() {
+    let interval: u64 = match config_or("WATCH_INTERVAL", 900) {
+        Ok(interval) => interval,
+        Err(e) => {
+            report(e);
+            return;
+        }
+    };
+    loop {
+        let conn = get_conn();
+        if let Err(e) = end_snoozes(&conn, "watch") {
+            report(e);
+        }
+        let ws = list_watch(&conn).unwrap_or_else(|e| {
+            report(e);
+            vec![]
+        });
+        drop(conn);
+        for w in ws.iter().filter(|w| !w.health.paused && !w.pause.held()) {
+            let res = check(w).await;
+            record(Kind::Watch, w.id, &w.repo, res.as_ref().copied()).await;
+        }
+        sleep(interval).await;
+    }
+}
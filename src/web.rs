@@ -0,0 +1,270 @@
+use crate::bot::Module;
+use crate::db::{get_conn, list_entry, Entry};
+use crate::dispatcher::Dispatcher;
+use crate::error::{report, MyError};
+use crate::utils::config_or;
+use futures::future::BoxFuture;
+use log::{error, info};
+use serde_json::json;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// how many items a served feed has
+const FEED_LIMIT: usize = 50;
+
+/// how much of a request is read, the request line and headers together
+const HEAD_LIMIT: u64 = 8 << 10;
+
+/// how long a client has to send its request and take the answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Format {
+    Atom,
+    Json,
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn rfc3339(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+fn atom(title: &str, id: &str, entries: &[Entry]) -> String {
+    let updated = entries.first().map(|e| e.time).unwrap_or_default();
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<feed xmlns=\"http://www.w3.org/2005/Atom\">
+<title>{}</title>
+<id>urn:turtlebot:{}</id>
+<updated>{}</updated>
+",
+        escape_xml(title),
+        escape_xml(id),
+        rfc3339(updated)
+    );
+    for e in entries {
+        xml.push_str(&format!(
+            "<entry>
+<title>{}</title>
+<link href=\"{}\"/>
+<id>urn:turtlebot:entry:{}</id>
+<updated>{}</updated>
+<author><name>{}</name></author>
+<summary>{}</summary>
+</entry>
+",
+            escape_xml(&e.title),
+            escape_xml(&e.link),
+            e.id,
+            rfc3339(e.time),
+            escape_xml(&e.source),
+            escape_xml(&e.summary)
+        ));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn json_feed(title: &str, entries: &[Entry]) -> String {
+    let items = entries
+        .iter()
+        .map(|e| {
+            json!({
+                "id": e.id.to_string(),
+                "url": e.link,
+                "title": e.title,
+                "content_text": e.summary,
+                "date_published": rfc3339(e.time),
+                "authors": [{"name": e.source}],
+                "tags": [e.kind],
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": title,
+        "items": items,
+    })
+    .to_string()
+}
+
+/// compare without leaking how much of the token was right
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// the status, content type and body for a path like `/<token>/all.atom`,
+/// `/<token>/rss.json` or `/<token>/rss/5.atom`
+fn respond(path: &str, token: &str) -> (&'static str, &'static str, String) {
+    const NOT_FOUND: (&str, &str, String) = ("404 Not Found", "text/plain", String::new());
+    let path = path.split('?').next().unwrap_or_default();
+    let mut segments = path.trim_start_matches('/').split('/');
+    if !segments.next().is_some_and(|t| token_eq(t, token)) {
+        return NOT_FOUND;
+    }
+    let rest = segments.collect::<Vec<_>>();
+    let (name, format) = match rest.last().map(|last| last.rsplit_once('.')) {
+        Some(Some((name, "atom"))) => (name, Format::Atom),
+        Some(Some((name, "json"))) => (name, Format::Json),
+        _ => return NOT_FOUND,
+    };
+    let (kind, sub_id) = match (rest.len(), name) {
+        (1, "all") => (None, None),
+        (1, kind) => (Some(kind), None),
+        (2, id) => match id.parse::<i32>() {
+            Ok(id) => (Some(rest[0]), Some(id)),
+            Err(_) => return NOT_FOUND,
+        },
+        _ => return NOT_FOUND,
+    };
+    if kind.is_some_and(|k| !matches!(k, "rss" | "repo" | "page" | "watch")) {
+        return NOT_FOUND;
+    }
+    let entries = match list_entry(&get_conn(), kind, sub_id, FEED_LIMIT) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report(e);
+            return ("500 Internal Server Error", "text/plain", String::new());
+        }
+    };
+    let title = match (kind, sub_id) {
+        (Some(_), Some(_)) => entries
+            .first()
+            .map(|e| e.source.clone())
+            .unwrap_or_else(|| name.to_owned()),
+        (Some(kind), None) => format!("turtlebot {}", kind),
+        _ => "turtlebot".to_owned(),
+    };
+    let id = rest.join("/");
+    match format {
+        Format::Atom => (
+            "200 OK",
+            "application/atom+xml",
+            atom(&title, &id, &entries),
+        ),
+        Format::Json => (
+            "200 OK",
+            "application/feed+json",
+            json_feed(&title, &entries),
+        ),
+    }
+}
+
+async fn handle(mut stream: TcpStream, token: &str) -> std::io::Result<()> {
+    // a longer head is cut short and gets no useful answer
+    let mut reader = BufReader::new((&mut stream).take(HEAD_LIMIT));
+    let mut request = String::new();
+    reader.read_line(&mut request).await?;
+    // skip the headers, nothing in them matters
+    let mut header = String::new();
+    while reader.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+    let (status, content_type, body) = match request.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", path, _] => respond(path, token),
+        _ => ("405 Method Not Allowed", "text/plain", String::new()),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// serves the delivered items as feeds at `FEED_ADDR`, to those with `FEED_TOKEN`
+pub struct FeedServer {}
+
+impl Module for FeedServer {
+    fn name(&self) -> &'static str {
+        "web"
+    }
+
+    fn register(&self, _: &mut Dispatcher) {}
+
+    fn check_settings(&self) -> Result<(), MyError> {
+        settings().map(|_| ())
+    }
+
+    fn task(&self) -> Option<BoxFuture<'static, ()>> {
+        Some(Box::pin(serve()))
+    }
+}
+
+/// where to serve the feeds and the token they need, `None` without
+/// `FEED_ADDR`; there are no feeds without a token
+fn settings() -> Result<Option<(String, String)>, MyError> {
+    let addr: String = config_or("FEED_ADDR", String::new())?;
+    if addr.is_empty() {
+        return Ok(None);
+    }
+    if addr
+        .rsplit_once(':')
+        .and_then(|(_, port)| port.parse::<u16>().ok())
+        .is_none()
+    {
+        return Err(MyError::Config {
+            name: "FEED_ADDR".to_owned(),
+            reason: format!("{:?} is not a host:port", addr),
+        });
+    }
+    let token: String = config_or("FEED_TOKEN", String::new())?;
+    if token.is_empty() {
+        return Err(MyError::Config {
+            name: "FEED_TOKEN".to_owned(),
+            reason: "feeds are not served without a token".to_owned(),
+        });
+    }
+    Ok(Some((addr, token)))
+}
+
+async fn serve() {
+    let (addr, token) = match settings() {
+        Ok(Some(settings)) => settings,
+        Ok(None) => return,
+        Err(e) => {
+            report(e);
+            return;
+        }
+    };
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("can not serve feeds on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("serving feeds on {}", addr);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        let token = token.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(REQUEST_TIMEOUT, handle(stream, &token)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("serve feed error: {}", e),
+                Err(_) => error!("serve feed error: client too slow"),
+            }
+        });
+    }
+}